@@ -0,0 +1,550 @@
+//! Minimal escape-sequence parser for PTY output.
+//!
+//! This is not a terminal emulator: it only splits the raw byte stream into
+//! printable text, C0 controls and complete CSI/OSC/DCS/ESC sequences so the
+//! backend can strip output or react to specific sequences. Parser state is
+//! kept across calls, so sequences split between two reads are handled.
+
+/// Maximum bytes collected for a single OSC payload; the rest is dropped
+const MAX_OSC_LEN: usize = 64 * 1024;
+
+/// Maximum number of CSI/DCS parameters tracked per sequence
+const MAX_PARAMS: usize = 32;
+
+const BEL: u8 = 0x07;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1a;
+const ESC: u8 = 0x1b;
+const DEL: u8 = 0x7f;
+
+/// Callbacks invoked by [`AnsiParser`] as it recognizes parts of the stream
+pub trait Perform {
+    /// A run of printable bytes. UTF-8 sequences may be split across calls.
+    fn print(&mut self, _bytes: &[u8]) {}
+
+    /// A C0 control byte such as BEL, BS, HT, LF or CR
+    fn execute(&mut self, _byte: u8) {}
+
+    /// A complete CSI sequence. `prefix` is the private marker (`?`, `>`, `<`, `=`) if any.
+    fn csi_dispatch(
+        &mut self,
+        _prefix: Option<u8>,
+        _params: &[u16],
+        _intermediates: &[u8],
+        _action: u8,
+    ) {
+    }
+
+    /// A complete ESC sequence that is not a CSI/OSC/DCS/SOS/PM/APC introducer
+    fn esc_dispatch(&mut self, _intermediates: &[u8], _byte: u8) {}
+
+    /// A complete OSC payload, without the `ESC ]` introducer and the terminator
+    fn osc_dispatch(&mut self, _data: &[u8]) {}
+
+    /// Start of a DCS sequence, called once its header is complete
+    fn dcs_hook(&mut self, _params: &[u16], _intermediates: &[u8], _action: u8) {}
+
+    /// End of the DCS sequence started by the last `dcs_hook`
+    fn dcs_unhook(&mut self) {}
+
+    /// Called after any dispatch with the span of the sequence in the slice passed
+    /// to [`AnsiParser::advance`]. `start` is `None` if the sequence began in an
+    /// earlier slice; `end` is the offset just past its last byte.
+    fn sequence_span(&mut self, _start: Option<usize>, _end: usize) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    CsiEntry,
+    CsiParam,
+    CsiIntermediate,
+    CsiIgnore,
+    OscString,
+    OscEscape,
+    DcsEntry,
+    DcsPassthrough,
+    DcsEscape,
+    IgnoredString,
+    IgnoredEscape,
+}
+
+/// Stateful byte-level parser for terminal output
+#[derive(Debug)]
+pub struct AnsiParser {
+    state: State,
+    prefix: Option<u8>,
+    params: Vec<u16>,
+    current_param: Option<u16>,
+    intermediates: Vec<u8>,
+    osc: Vec<u8>,
+    /// Where the current sequence began in the slice being parsed
+    sequence_start: Option<usize>,
+}
+
+impl Default for AnsiParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnsiParser {
+    pub fn new() -> Self {
+        Self {
+            state: State::Ground,
+            prefix: None,
+            params: Vec::new(),
+            current_param: None,
+            intermediates: Vec::new(),
+            osc: Vec::new(),
+            sequence_start: None,
+        }
+    }
+
+    /// Whether the parser is in the middle of an escape sequence
+    pub fn in_sequence(&self) -> bool {
+        self.state != State::Ground
+    }
+
+    /// After [`advance`](Self::advance): where the unfinished trailing sequence began.
+    /// `Some(Some(offset))` if it began in the last slice, `Some(None)` if even earlier,
+    /// `None` if the parser is not in a sequence.
+    pub fn unfinished_sequence_start(&self) -> Option<Option<usize>> {
+        self.in_sequence().then_some(self.sequence_start)
+    }
+
+    /// Feed raw bytes to the parser, invoking `performer` for everything recognized
+    pub fn advance<P: Perform>(&mut self, bytes: &[u8], performer: &mut P) {
+        let mut run_start: Option<usize> = None;
+        // A sequence carried over from the previous slice has no start in this one
+        self.sequence_start = None;
+
+        for (i, &byte) in bytes.iter().enumerate() {
+            if self.state == State::Ground && is_printable(byte) {
+                if run_start.is_none() {
+                    run_start = Some(i);
+                }
+                continue;
+            }
+
+            if let Some(start) = run_start.take() {
+                performer.print(&bytes[start..i]);
+            }
+            let from_ground = self.state == State::Ground;
+            let dispatched = self.advance_byte(byte, performer);
+            if dispatched {
+                let start = if from_ground {
+                    Some(i)
+                } else {
+                    self.sequence_start
+                };
+                performer.sequence_span(start, i + 1);
+            }
+            if from_ground && self.state != State::Ground {
+                self.sequence_start = Some(i);
+            }
+        }
+
+        if let Some(start) = run_start {
+            performer.print(&bytes[start..]);
+        }
+    }
+
+    /// Process one byte outside a printable run. Returns true if something was dispatched.
+    fn advance_byte<P: Perform>(&mut self, byte: u8, performer: &mut P) -> bool {
+        // CAN and SUB abort any sequence in progress
+        if byte == CAN || byte == SUB {
+            let hooked = matches!(self.state, State::DcsPassthrough | State::DcsEscape);
+            if hooked {
+                performer.dcs_unhook();
+            }
+            self.state = State::Ground;
+            return hooked;
+        }
+
+        match self.state {
+            State::Ground => match byte {
+                ESC => self.enter_escape(),
+                DEL => {}
+                _ => performer.execute(byte),
+            },
+            State::Escape => match byte {
+                ESC => self.enter_escape(),
+                b'[' => self.enter_params(State::CsiEntry),
+                b']' => {
+                    self.osc.clear();
+                    self.state = State::OscString;
+                }
+                b'P' => self.enter_params(State::DcsEntry),
+                b'X' | b'^' | b'_' => self.state = State::IgnoredString,
+                0x20..=0x2f => {
+                    self.intermediates.push(byte);
+                    self.state = State::EscapeIntermediate;
+                }
+                0x30..=0x7e => {
+                    performer.esc_dispatch(&self.intermediates, byte);
+                    self.state = State::Ground;
+                    return true;
+                }
+                DEL => {}
+                _ if byte < 0x20 => performer.execute(byte),
+                _ => self.state = State::Ground,
+            },
+            State::EscapeIntermediate => match byte {
+                ESC => self.enter_escape(),
+                0x20..=0x2f => {
+                    if self.intermediates.len() < MAX_PARAMS {
+                        self.intermediates.push(byte);
+                    }
+                }
+                0x30..=0x7e => {
+                    performer.esc_dispatch(&self.intermediates, byte);
+                    self.state = State::Ground;
+                    return true;
+                }
+                DEL => {}
+                _ if byte < 0x20 => performer.execute(byte),
+                _ => self.state = State::Ground,
+            },
+            State::CsiEntry | State::CsiParam | State::CsiIntermediate => {
+                return self.advance_csi(byte, performer);
+            }
+            State::CsiIgnore => match byte {
+                ESC => self.enter_escape(),
+                0x40..=0x7e => self.state = State::Ground,
+                _ if byte < 0x20 => performer.execute(byte),
+                _ => {}
+            },
+            State::OscString => match byte {
+                BEL => {
+                    performer.osc_dispatch(&self.osc);
+                    self.state = State::Ground;
+                    return true;
+                }
+                ESC => self.state = State::OscEscape,
+                _ if byte < 0x20 => {}
+                _ => {
+                    if self.osc.len() < MAX_OSC_LEN {
+                        self.osc.push(byte);
+                    }
+                }
+            },
+            State::OscEscape => {
+                // `ESC \` (ST) terminates the string; any other ESC sequence also ends it
+                performer.osc_dispatch(&self.osc);
+                return self.finish_string_escape(byte, performer);
+            }
+            State::DcsEntry => match byte {
+                ESC => self.enter_escape(),
+                0x3c..=0x3f if self.params.is_empty() && self.current_param.is_none() => {
+                    self.prefix = Some(byte);
+                }
+                b'0'..=b'9' => self.push_digit(byte),
+                b';' | b':' => self.finish_param(),
+                0x20..=0x2f => {
+                    if self.intermediates.len() < MAX_PARAMS {
+                        self.intermediates.push(byte);
+                    }
+                }
+                0x40..=0x7e => {
+                    self.finish_params();
+                    performer.dcs_hook(&self.params, &self.intermediates, byte);
+                    self.state = State::DcsPassthrough;
+                    return true;
+                }
+                _ => {}
+            },
+            State::DcsPassthrough => {
+                if byte == ESC {
+                    self.state = State::DcsEscape;
+                }
+            }
+            State::DcsEscape => {
+                performer.dcs_unhook();
+                return self.finish_string_escape(byte, performer);
+            }
+            State::IgnoredString => {
+                if byte == ESC {
+                    self.state = State::IgnoredEscape;
+                }
+            }
+            State::IgnoredEscape => {
+                self.finish_string_escape(byte, performer);
+            }
+        }
+        false
+    }
+
+    /// Handle the byte after an ESC inside a string sequence. A backslash
+    /// completes the ST terminator; anything else starts a new ESC sequence.
+    fn finish_string_escape<P: Perform>(&mut self, byte: u8, performer: &mut P) -> bool {
+        if byte == b'\\' {
+            self.state = State::Ground;
+            return true;
+        }
+        self.enter_escape();
+        self.advance_byte(byte, performer);
+        true
+    }
+
+    fn advance_csi<P: Perform>(&mut self, byte: u8, performer: &mut P) -> bool {
+        match byte {
+            ESC => self.enter_escape(),
+            0x3c..=0x3f => {
+                if self.state == State::CsiEntry {
+                    self.prefix = Some(byte);
+                    self.state = State::CsiParam;
+                } else {
+                    self.state = State::CsiIgnore;
+                }
+            }
+            b'0'..=b'9' if self.state != State::CsiIntermediate => {
+                self.push_digit(byte);
+                self.state = State::CsiParam;
+            }
+            b';' | b':' if self.state != State::CsiIntermediate => {
+                self.finish_param();
+                self.state = State::CsiParam;
+            }
+            0x20..=0x2f => {
+                if self.intermediates.len() < MAX_PARAMS {
+                    self.intermediates.push(byte);
+                }
+                self.state = State::CsiIntermediate;
+            }
+            0x40..=0x7e => {
+                self.finish_params();
+                performer.csi_dispatch(self.prefix, &self.params, &self.intermediates, byte);
+                self.state = State::Ground;
+                return true;
+            }
+            DEL => {}
+            _ if byte < 0x20 => performer.execute(byte),
+            _ => self.state = State::CsiIgnore,
+        }
+        false
+    }
+
+    fn enter_escape(&mut self) {
+        self.intermediates.clear();
+        self.state = State::Escape;
+    }
+
+    fn enter_params(&mut self, state: State) {
+        self.prefix = None;
+        self.params.clear();
+        self.current_param = None;
+        self.intermediates.clear();
+        self.state = state;
+    }
+
+    fn push_digit(&mut self, byte: u8) {
+        let digit = u16::from(byte - b'0');
+        let value = self.current_param.unwrap_or(0);
+        self.current_param = Some(value.saturating_mul(10).saturating_add(digit));
+    }
+
+    /// Terminate the current parameter on a `;` / `:` separator
+    fn finish_param(&mut self) {
+        if self.params.len() < MAX_PARAMS {
+            self.params.push(self.current_param.unwrap_or(0));
+        }
+        self.current_param = None;
+    }
+
+    /// Push the trailing parameter before dispatching
+    fn finish_params(&mut self) {
+        if let Some(value) = self.current_param.take() {
+            if self.params.len() < MAX_PARAMS {
+                self.params.push(value);
+            }
+        } else if !self.params.is_empty() && self.params.len() < MAX_PARAMS {
+            // "1;" means the last parameter was given but left empty
+            self.params.push(0);
+        }
+    }
+}
+
+fn is_printable(byte: u8) -> bool {
+    byte >= 0x20 && byte != DEL
+}
+
+/// Incremental UTF-8 decoder that keeps incomplete trailing sequences for the next call
+#[derive(Debug, Default)]
+pub struct Utf8Decoder {
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode `bytes` into `out`, replacing invalid sequences with U+FFFD.
+    /// A multi-byte character cut off at the end is held back until the next call.
+    pub fn decode(&mut self, bytes: &[u8], out: &mut String) {
+        let owned;
+        let mut input: &[u8] = if self.pending.is_empty() {
+            bytes
+        } else {
+            self.pending.extend_from_slice(bytes);
+            owned = std::mem::take(&mut self.pending);
+            &owned
+        };
+
+        loop {
+            match std::str::from_utf8(input) {
+                Ok(text) => {
+                    out.push_str(text);
+                    return;
+                }
+                Err(e) => {
+                    let (valid, rest) = input.split_at(e.valid_up_to());
+                    out.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                    match e.error_len() {
+                        Some(len) => {
+                            out.push(char::REPLACEMENT_CHARACTER);
+                            input = &rest[len..];
+                        }
+                        None => {
+                            self.pending.extend_from_slice(rest);
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Stateful ANSI stripper that turns raw PTY output into plain text.
+/// Keeps printable text, newlines and tabs; drops escape sequences and other controls.
+#[derive(Debug, Default)]
+pub struct AnsiStripper {
+    parser: AnsiParser,
+    decoder: Utf8Decoder,
+}
+
+struct StripPerform<'a> {
+    text: &'a mut Vec<u8>,
+}
+
+impl Perform for StripPerform<'_> {
+    fn print(&mut self, bytes: &[u8]) {
+        self.text.extend_from_slice(bytes);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        if byte == b'\n' || byte == b'\t' {
+            self.text.push(byte);
+        }
+    }
+}
+
+impl AnsiStripper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Strip a chunk of raw output. Sequences split across chunks are handled.
+    pub fn strip(&mut self, bytes: &[u8]) -> String {
+        let mut raw = Vec::with_capacity(bytes.len());
+        self.parser
+            .advance(bytes, &mut StripPerform { text: &mut raw });
+        let mut text = String::with_capacity(raw.len());
+        self.decoder.decode(&raw, &mut text);
+        text
+    }
+}
+
+/// Strip ANSI escape sequences from a complete string
+pub fn strip_ansi(input: &str) -> String {
+    AnsiStripper::new().strip(input.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        csi: Vec<(Option<u8>, Vec<u16>, u8)>,
+        osc: Vec<String>,
+        controls: Vec<u8>,
+    }
+
+    impl Perform for Recorder {
+        fn execute(&mut self, byte: u8) {
+            self.controls.push(byte);
+        }
+
+        fn csi_dispatch(&mut self, prefix: Option<u8>, params: &[u16], _: &[u8], action: u8) {
+            self.csi.push((prefix, params.to_vec(), action));
+        }
+
+        fn osc_dispatch(&mut self, data: &[u8]) {
+            self.osc.push(String::from_utf8_lossy(data).to_string());
+        }
+    }
+
+    #[test]
+    fn test_strip_removes_csi_and_osc() {
+        let input = "\x1b[1;32mgreen\x1b[0m \x1b]0;title\x07plain\r\n";
+        assert_eq!(strip_ansi(input), "green plain\n");
+    }
+
+    #[test]
+    fn test_strip_handles_sequence_split_across_chunks() {
+        let mut stripper = AnsiStripper::new();
+        let mut text = stripper.strip(b"before\x1b[3");
+        text.push_str(&stripper.strip(b"1mafter\x1b]2;ti"));
+        text.push_str(&stripper.strip(b"tle\x1b\\done"));
+        assert_eq!(text, "beforeafterdone");
+    }
+
+    #[test]
+    fn test_strip_handles_utf8_split_across_chunks() {
+        let bytes = "héllo 🎉".as_bytes();
+        let split = bytes.len() - 2;
+        let mut stripper = AnsiStripper::new();
+        let mut text = stripper.strip(&bytes[..split]);
+        text.push_str(&stripper.strip(&bytes[split..]));
+        assert_eq!(text, "héllo 🎉");
+    }
+
+    #[test]
+    fn test_parser_reports_csi_params() {
+        let mut parser = AnsiParser::new();
+        let mut recorder = Recorder::default();
+        parser.advance(b"\x1b[?1049h\x1b[12;40H\x1b[m", &mut recorder);
+        assert_eq!(
+            recorder.csi,
+            vec![
+                (Some(b'?'), vec![1049], b'h'),
+                (None, vec![12, 40], b'H'),
+                (None, vec![], b'm'),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parser_osc_terminators() {
+        let mut parser = AnsiParser::new();
+        let mut recorder = Recorder::default();
+        parser.advance(b"\x1b]7;file:///tmp\x07\x1b]0;hi\x1b\\", &mut recorder);
+        assert_eq!(recorder.osc, vec!["7;file:///tmp", "0;hi"]);
+        // BEL terminating an OSC is not reported as a control
+        assert!(recorder.controls.is_empty());
+    }
+
+    #[test]
+    fn test_utf8_decoder_replaces_invalid_bytes() {
+        let mut decoder = Utf8Decoder::new();
+        let mut out = String::new();
+        decoder.decode(b"a\xffb", &mut out);
+        assert_eq!(out, "a\u{FFFD}b");
+        assert!(decoder.pending.is_empty());
+    }
+}
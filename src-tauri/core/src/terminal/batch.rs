@@ -0,0 +1,192 @@
+//! Compound actions such as "clear, resize and run a command" issued as one
+//! ordered batch, so nothing else touches the session in between.
+
+use super::events;
+use super::paste::paste_data;
+use super::{resize_session, write_session, PtySession, PTY_SESSIONS};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tauri::AppHandle;
+
+/// One operation of a `pty_batch`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum PtyOp {
+    /// Same as `pty_resize`
+    Resize { cols: u16, rows: u16 },
+    /// Drop the retained scrollback
+    Clear,
+    /// Same as `pty_write`
+    Write { data: String },
+    /// Write pasted text: line breaks become carriage returns, and with
+    /// `bracketed` (the program enabled bracketed paste) it's wrapped in paste
+    /// markers
+    Paste {
+        data: String,
+        #[serde(default)]
+        bracketed: bool,
+    },
+    /// Send a signal, e.g. `SIGINT`, to the foreground process group (Unix only)
+    Signal { signal: String },
+}
+
+/// Outcome of one operation of a `pty_batch`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PtyOpResult {
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
+impl From<Result<(), String>> for PtyOpResult {
+    fn from(result: Result<(), String>) -> Self {
+        match result {
+            Ok(()) => Self {
+                ok: true,
+                error: None,
+            },
+            Err(error) => Self {
+                ok: false,
+                error: Some(error),
+            },
+        }
+    }
+}
+
+/// Run `ops` in order under a single lock of the session registry, so no other
+/// command can interleave. Stops at the first failing operation; the ones after
+/// it are reported as skipped. Returns one result per operation.
+#[tauri::command]
+pub fn pty_batch(
+    app: AppHandle,
+    pty_id: String,
+    ops: Vec<PtyOp>,
+) -> Result<Vec<PtyOpResult>, String> {
+    let started = Instant::now();
+    let mut sessions = PTY_SESSIONS.lock().unwrap();
+    let session = sessions
+        .get_mut(&pty_id)
+        .ok_or_else(|| format!("PTY session {} not found", pty_id))?;
+    info!("Running {} operations on PTY {}", ops.len(), pty_id);
+
+    let sink = events::sink(&app);
+    let mut results = Vec::with_capacity(ops.len());
+    let mut failed = false;
+    for op in ops {
+        if failed {
+            results.push(PtyOpResult::from(Err(
+                "Skipped after an earlier operation failed".to_string(),
+            )));
+            continue;
+        }
+        let result = match op {
+            PtyOp::Resize { cols, rows } => resize_session(&app, &pty_id, session, cols, rows),
+            PtyOp::Clear => {
+                session.output.lock().unwrap().scrollback.clear();
+                Ok(())
+            }
+            PtyOp::Write { data } => write_session(&sink, &pty_id, session, data, started),
+            PtyOp::Paste { data, bracketed } => write_session(
+                &sink,
+                &pty_id,
+                session,
+                paste_data(&data, bracketed),
+                started,
+            ),
+            PtyOp::Signal { signal } => send_signal(session, &signal),
+        };
+        if let Err(e) = &result {
+            warn!("Batch operation on PTY {} failed: {}", pty_id, e);
+            failed = true;
+        }
+        results.push(result.into());
+    }
+    Ok(results)
+}
+
+#[cfg(unix)]
+fn parse_signal(name: &str) -> Option<libc::c_int> {
+    let signal = match name.trim_start_matches("SIG") {
+        "INT" => libc::SIGINT,
+        "TERM" => libc::SIGTERM,
+        "HUP" => libc::SIGHUP,
+        "QUIT" => libc::SIGQUIT,
+        "KILL" => libc::SIGKILL,
+        "TSTP" => libc::SIGTSTP,
+        "CONT" => libc::SIGCONT,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "WINCH" => libc::SIGWINCH,
+        _ => return None,
+    };
+    Some(signal)
+}
+
+/// Send a signal by name, e.g. `SIGINT`, to the foreground job of a session
+#[cfg(unix)]
+pub fn send_signal(session: &PtySession, name: &str) -> Result<(), String> {
+    let signal = parse_signal(name).ok_or_else(|| format!("Unknown signal {}", name))?;
+    // The foreground job, e.g. a command running in the shell, else the shell
+    let target = match session.master.process_group_leader() {
+        Some(group) => -group,
+        None => session
+            .child
+            .process_id()
+            .ok_or_else(|| "The shell has already exited".to_string())?
+            as libc::pid_t,
+    };
+    if unsafe { libc::kill(target, signal) } != 0 {
+        return Err(format!(
+            "Failed to send {}: {}",
+            name,
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn send_signal(_session: &PtySession, _name: &str) -> Result<(), String> {
+    Err("Signals are only supported on Unix".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ops_deserialize_with_op_tag() {
+        let ops: Vec<PtyOp> = serde_json::from_value(serde_json::json!([
+            { "op": "clear" },
+            { "op": "resize", "cols": 120, "rows": 40 },
+            { "op": "paste", "data": "x" },
+            { "op": "signal", "signal": "SIGINT" },
+        ]))
+        .unwrap();
+        assert_eq!(
+            ops,
+            vec![
+                PtyOp::Clear,
+                PtyOp::Resize {
+                    cols: 120,
+                    rows: 40
+                },
+                PtyOp::Paste {
+                    data: "x".to_string(),
+                    bracketed: false
+                },
+                PtyOp::Signal {
+                    signal: "SIGINT".to_string()
+                },
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("SIGINT"), Some(libc::SIGINT));
+        assert_eq!(parse_signal("TERM"), Some(libc::SIGTERM));
+        assert_eq!(parse_signal("SIGBOGUS"), None);
+    }
+}
@@ -0,0 +1,90 @@
+//! Bell (`\a`) detection with rate limiting, so a bell storm from a broken
+//! script doesn't flash the UI for every bell.
+
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Default minimum interval between two `pty-bell` events
+pub const DEFAULT_BELL_WINDOW_MS: u64 = 200;
+
+/// Payload of the `pty-bell` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PtyBell {
+    pub pty_id: String,
+    /// Bells suppressed by the rate limit since the previous `pty-bell`
+    pub suppressed: u32,
+}
+
+/// Lets at most one bell through per window and counts the rest
+#[derive(Debug)]
+pub struct BellLimiter {
+    window: Duration,
+    last_emit: Option<Instant>,
+    suppressed: u32,
+}
+
+impl BellLimiter {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last_emit: None,
+            suppressed: 0,
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Record `count` bells seen at `now`. Returns the number of bells suppressed
+    /// since the last emitted one when a `pty-bell` should be emitted.
+    pub fn ring(&mut self, now: Instant, count: u32) -> Option<u32> {
+        if count == 0 {
+            return None;
+        }
+
+        let ready = self
+            .last_emit
+            .is_none_or(|last| now.duration_since(last) >= self.window);
+        if !ready {
+            self.suppressed = self.suppressed.saturating_add(count);
+            return None;
+        }
+
+        // One bell of this batch is emitted; the others are aggregated
+        let suppressed = self.suppressed.saturating_add(count - 1);
+        self.last_emit = Some(now);
+        self.suppressed = 0;
+        Some(suppressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bell_storm_is_rate_limited() {
+        let mut limiter = BellLimiter::new(Duration::from_millis(DEFAULT_BELL_WINDOW_MS));
+        let start = Instant::now();
+
+        // 100 bells within 100ms
+        let emitted: Vec<u32> = (0..100)
+            .filter_map(|i| limiter.ring(start + Duration::from_millis(i), 1))
+            .collect();
+        assert_eq!(emitted, vec![0]);
+
+        // The next bell after the window reports everything that was suppressed
+        assert_eq!(
+            limiter.ring(start + Duration::from_millis(250), 1),
+            Some(99)
+        );
+        assert_eq!(limiter.ring(start + Duration::from_millis(260), 1), None);
+    }
+
+    #[test]
+    fn test_bells_in_one_chunk_are_aggregated() {
+        let mut limiter = BellLimiter::new(Duration::from_millis(DEFAULT_BELL_WINDOW_MS));
+        assert_eq!(limiter.ring(Instant::now(), 100), Some(99));
+    }
+}
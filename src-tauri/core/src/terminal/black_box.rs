@@ -0,0 +1,167 @@
+//! Always-on "black box" recorder: a small ring of the last seconds of raw
+//! input and output of a session, written to a diagnostics file only when the
+//! session crashes, so a report can include the moments before a terminal
+//! misbehaved. It holds whatever was typed, passwords included, and never
+//! leaves memory unless dumped; set `black_box_bytes` to 0 to turn it off.
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Default size of the ring
+pub const DEFAULT_BLACK_BOX_BYTES: usize = 64 * 1024;
+
+/// I/O older than this is dropped even when the ring has room
+const BLACK_BOX_WINDOW: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Input,
+    Output,
+    /// Output put in by the app, e.g. seeded scrollback, rather than read from
+    /// the PTY
+    Injected,
+}
+
+#[derive(Debug)]
+struct Record {
+    at: Instant,
+    direction: Direction,
+    data: Vec<u8>,
+}
+
+/// Ring of recent raw I/O, bounded by size and age
+#[derive(Debug)]
+pub struct BlackBox {
+    records: VecDeque<Record>,
+    bytes: usize,
+    cap: usize,
+}
+
+impl BlackBox {
+    pub fn new(cap: usize) -> Self {
+        Self {
+            records: VecDeque::new(),
+            bytes: 0,
+            cap,
+        }
+    }
+
+    pub fn cap(&self) -> usize {
+        self.cap
+    }
+
+    pub fn record(&mut self, direction: Direction, data: &[u8]) {
+        self.record_at(Instant::now(), direction, data);
+    }
+
+    fn record_at(&mut self, at: Instant, direction: Direction, data: &[u8]) {
+        let data = &data[data.len().saturating_sub(self.cap)..];
+        if data.is_empty() {
+            return;
+        }
+        self.records.push_back(Record {
+            at,
+            direction,
+            data: data.to_vec(),
+        });
+        self.bytes += data.len();
+
+        while let Some(front) = self.records.front() {
+            let expired = at.saturating_duration_since(front.at) > BLACK_BOX_WINDOW;
+            if self.bytes <= self.cap && !expired {
+                break;
+            }
+            self.bytes -= front.data.len();
+            self.records.pop_front();
+        }
+    }
+
+    /// Write the recorded I/O to a new file in `dir` and return its path. Each
+    /// record is one line with its time before the crash, `>` for input, `<`
+    /// for output or `+` for injected output, and the bytes with control
+    /// characters escaped.
+    pub fn dump(&self, dir: &Path, pty_id: &str, reason: &str) -> std::io::Result<PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis());
+        let path = dir.join(format!("pty-{}-{}.log", pty_id, stamp));
+
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+        writeln!(file, "PTY {} crashed: {}", pty_id, reason)?;
+        let now = Instant::now();
+        for record in &self.records {
+            writeln!(
+                file,
+                "-{:>6}ms {} {}",
+                now.saturating_duration_since(record.at).as_millis(),
+                match record.direction {
+                    Direction::Input => '>',
+                    Direction::Output => '<',
+                    Direction::Injected => '+',
+                },
+                String::from_utf8_lossy(&record.data).escape_debug()
+            )?;
+        }
+        file.flush()?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_black_box_keeps_most_recent_bytes() {
+        let mut black_box = BlackBox::new(8);
+        let now = Instant::now();
+        black_box.record_at(now, Direction::Output, b"aaaa");
+        black_box.record_at(now, Direction::Input, b"bbbb");
+        black_box.record_at(now, Direction::Output, b"cccc");
+        assert_eq!(black_box.bytes, 8);
+        assert_eq!(black_box.records.front().unwrap().data, b"bbbb");
+
+        // A record larger than the ring keeps its tail
+        black_box.record_at(now, Direction::Output, b"0123456789");
+        assert_eq!(black_box.records.len(), 1);
+        assert_eq!(black_box.records[0].data, b"23456789");
+    }
+
+    #[test]
+    fn test_black_box_drops_old_records() {
+        let mut black_box = BlackBox::new(1024);
+        let start = Instant::now();
+        black_box.record_at(start, Direction::Output, b"old");
+        black_box.record_at(
+            start + BLACK_BOX_WINDOW + Duration::from_secs(1),
+            Direction::Output,
+            b"new",
+        );
+        assert_eq!(black_box.records.len(), 1);
+        assert_eq!(black_box.records[0].data, b"new");
+    }
+
+    #[test]
+    fn test_black_box_dump() {
+        let mut black_box = BlackBox::new(1024);
+        black_box.record(Direction::Injected, b"old history\r\n");
+        black_box.record(Direction::Input, b"ls\r");
+        black_box.record(Direction::Output, b"\x1b[31mboom\x1b[0m\r\n");
+
+        let dir = std::env::temp_dir().join(format!("talkcody-{}", uuid::Uuid::new_v4()));
+        let path = black_box
+            .dump(&dir, "abc", "child terminated by SIGSEGV")
+            .unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines[0], "PTY abc crashed: child terminated by SIGSEGV");
+        assert!(lines[1].ends_with("+ old history\\r\\n"));
+        assert!(lines[2].ends_with("> ls\\r"));
+        assert!(lines[3].ends_with("< \\u{1b}[31mboom\\u{1b}[0m\\r\\n"));
+    }
+}
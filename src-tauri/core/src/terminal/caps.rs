@@ -0,0 +1,106 @@
+//! Which escape sequences the backend parses for a session, so the frontend
+//! can avoid handling them twice.
+
+use super::control_mode::ControlProtocol;
+use serde::{Deserialize, Serialize};
+
+/// Returned by `pty_parser_capabilities`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParserCaps {
+    pub alt_screen: AltScreenCap,
+    pub cwd: CwdCap,
+    pub command_marks: CommandMarksCap,
+    pub hyperlinks: HyperlinksCap,
+    pub bell: BellCap,
+    pub title: TitleCap,
+    pub cursor_reports: CursorReportsCap,
+    pub size_reports: SizeReportsCap,
+    pub answerback: AnswerbackCap,
+    pub control_mode: ControlModeCap,
+}
+
+/// CSI ?47 / ?1047 / ?1049 h/l and RIS. Always parsed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AltScreenCap {
+    pub enabled: bool,
+    /// Whether a full-screen program is on the alternate screen
+    pub active: bool,
+    /// Whether alternate-screen output is kept in scrollback
+    pub captured: bool,
+}
+
+/// OSC 7 working directory reports. Always parsed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CwdCap {
+    pub enabled: bool,
+    /// Last reported directory
+    pub cwd: Option<String>,
+}
+
+/// OSC 133 command boundaries. Always parsed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandMarksCap {
+    pub enabled: bool,
+    /// Whether the shell was started with our integration scripts
+    pub shell_integration: bool,
+    /// Whether the shell has reported any boundary yet
+    pub seen: bool,
+    /// Whether a `pty_capture_next` call is waiting
+    pub capture_pending: bool,
+}
+
+/// OSC 8 hyperlinks. Parsed only when `hyperlink_events` is set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HyperlinksCap {
+    pub enabled: bool,
+    /// Whether link text is currently being printed
+    pub link_open: bool,
+}
+
+/// BEL, rate limited into `pty-bell` events. Always parsed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BellCap {
+    pub enabled: bool,
+    pub window_ms: u64,
+}
+
+/// OSC 0 / OSC 2 titles and the CSI 22/23 t title stack. Always parsed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TitleCap {
+    pub enabled: bool,
+    pub title: Option<String>,
+    /// Number of saved titles
+    pub stack_depth: usize,
+}
+
+/// CSI 6 n cursor position requests, answered by the backend only for
+/// `pty_run_stream` sessions started with `answer_cursor_queries`. The cursor
+/// is tracked for every session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CursorReportsCap {
+    pub enabled: bool,
+    /// Approximate 0-based (row, col) of the cursor
+    pub position: Option<(u16, u16)>,
+}
+
+/// CSI 18 t / CSI 14 t window size queries, answered by the backend. Parsed only
+/// for sessions spawned with `auto_respond_size`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SizeReportsCap {
+    pub enabled: bool,
+}
+
+/// ENQ, answered with the answerback string. Parsed only once one is set with
+/// `pty_set_answerback`; off by default.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnswerbackCap {
+    pub enabled: bool,
+}
+
+/// DCS headers that start a multiplexer control mode (`tmux -CC`). Always parsed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ControlModeCap {
+    pub enabled: bool,
+    /// Protocol being spoken, while in control mode
+    pub protocol: Option<ControlProtocol>,
+}
@@ -0,0 +1,104 @@
+//! Bounded history of closed sessions, kept after they leave the registry so
+//! "my terminal keeps disappearing" can be diagnosed after the fact.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Closed sessions remembered; older ones are forgotten
+const MAX_CLOSURES: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    /// The shell or command exited on its own
+    Exited,
+    /// Killed with `pty_kill` or `pty_kill_group`
+    Killed,
+    /// Terminated by a signal it wasn't sent by us, or reading from it failed
+    Crashed,
+}
+
+/// A closed session, returned by `pty_recent_closures`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClosureRecord {
+    pub pty_id: String,
+    pub name: Option<String>,
+    pub shell: String,
+    /// `None` when killed by a signal or the exit status couldn't be read
+    pub exit_code: Option<i32>,
+    /// Signal that terminated the child, e.g. `Segmentation fault`
+    pub signal: Option<String>,
+    pub reason: CloseReason,
+    /// Unix time in ms
+    pub closed_at_ms: u64,
+    /// Black box dump written because the session crashed
+    pub crash_dump: Option<String>,
+}
+
+lazy_static::lazy_static! {
+    static ref CLOSURES: Mutex<VecDeque<ClosureRecord>> = Mutex::new(VecDeque::new());
+}
+
+/// Current time for [`ClosureRecord::closed_at_ms`]
+pub(super) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+pub(super) fn record(closure: ClosureRecord) {
+    push(&mut CLOSURES.lock().unwrap(), closure);
+}
+
+fn push(closures: &mut VecDeque<ClosureRecord>, closure: ClosureRecord) {
+    if closures.len() == MAX_CLOSURES {
+        closures.pop_front();
+    }
+    closures.push_back(closure);
+}
+
+/// Recently closed sessions, most recent first
+#[tauri::command]
+pub fn pty_recent_closures() -> Vec<ClosureRecord> {
+    CLOSURES.lock().unwrap().iter().rev().cloned().collect()
+}
+
+/// Forget the closed sessions recorded so far
+#[tauri::command]
+pub fn pty_clear_closures() {
+    CLOSURES.lock().unwrap().clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn closure(pty_id: &str) -> ClosureRecord {
+        ClosureRecord {
+            pty_id: pty_id.to_string(),
+            name: None,
+            shell: "/bin/sh".to_string(),
+            exit_code: Some(0),
+            signal: None,
+            reason: CloseReason::Exited,
+            closed_at_ms: now_ms(),
+            crash_dump: None,
+        }
+    }
+
+    #[test]
+    fn test_closure_history_is_capped() {
+        let mut closures = VecDeque::new();
+        for i in 0..MAX_CLOSURES + 5 {
+            push(&mut closures, closure(&i.to_string()));
+        }
+        assert_eq!(closures.len(), MAX_CLOSURES);
+        assert_eq!(closures.front().unwrap().pty_id, "5");
+        assert_eq!(
+            closures.back().unwrap().pty_id,
+            (MAX_CLOSURES + 4).to_string()
+        );
+    }
+}
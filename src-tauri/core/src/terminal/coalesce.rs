@@ -0,0 +1,140 @@
+//! Output coalescing: output read within a short interval is batched into one
+//! `pty-output` event, trading a little latency for far fewer IPC events while
+//! a program prints heavily.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::time::{Duration, Instant};
+
+/// Default time output is held to batch it with what follows
+pub const DEFAULT_FLUSH_INTERVAL_MS: u64 = 5;
+
+/// Largest accepted flush interval
+pub const MAX_FLUSH_INTERVAL_MS: u64 = 1000;
+
+/// A batch is emitted early once it reaches this size
+const MAX_BATCH_BYTES: usize = 64 * 1024;
+
+/// Coalescing settings of a session, changeable while it runs
+#[derive(Debug)]
+pub struct FlushSettings {
+    interval_ms: AtomicU64,
+    low_latency: AtomicBool,
+}
+
+impl FlushSettings {
+    pub fn new(interval_ms: u64, low_latency: bool) -> Self {
+        Self {
+            interval_ms: AtomicU64::new(interval_ms),
+            low_latency: AtomicBool::new(low_latency),
+        }
+    }
+
+    pub fn interval_ms(&self) -> u64 {
+        self.interval_ms.load(Ordering::Relaxed)
+    }
+
+    pub fn set_interval_ms(&self, interval_ms: u64) {
+        self.interval_ms.store(interval_ms, Ordering::Relaxed);
+    }
+
+    pub fn low_latency(&self) -> bool {
+        self.low_latency.load(Ordering::Relaxed)
+    }
+
+    pub fn set_low_latency(&self, low_latency: bool) {
+        self.low_latency.store(low_latency, Ordering::Relaxed);
+    }
+
+    /// How long to wait for more output; low-latency mode never waits
+    fn wait(&self) -> Duration {
+        if self.low_latency() {
+            Duration::ZERO
+        } else {
+            Duration::from_millis(self.interval_ms())
+        }
+    }
+}
+
+pub fn validate_flush_interval(interval_ms: u64) -> Result<(), String> {
+    if interval_ms > MAX_FLUSH_INTERVAL_MS {
+        return Err(format!(
+            "Flush interval must be between 0 and {}ms, got {}ms",
+            MAX_FLUSH_INTERVAL_MS, interval_ms
+        ));
+    }
+    Ok(())
+}
+
+/// Wait for output and batch it with whatever arrives within the flush
+/// interval. Returns `None` once the sender is gone and nothing is left.
+pub fn next_batch(rx: &Receiver<String>, settings: &FlushSettings) -> Option<String> {
+    let mut batch = rx.recv().ok()?;
+    let deadline = Instant::now() + settings.wait();
+
+    while batch.len() < MAX_BATCH_BYTES {
+        // Output that is already queued never waits
+        match rx.try_recv() {
+            Ok(data) => {
+                batch.push_str(&data);
+                continue;
+            }
+            Err(TryRecvError::Disconnected) => break,
+            Err(TryRecvError::Empty) => {}
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        match rx.recv_timeout(deadline - now) {
+            Ok(data) => batch.push_str(&data),
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    Some(batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_output_within_interval_is_batched() {
+        let settings = FlushSettings::new(200, false);
+        let (tx, rx) = mpsc::channel();
+        tx.send("a".to_string()).unwrap();
+        let sender = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            tx.send("b".to_string()).unwrap();
+        });
+
+        assert_eq!(next_batch(&rx, &settings).as_deref(), Some("ab"));
+        sender.join().unwrap();
+        assert_eq!(next_batch(&rx, &settings), None);
+    }
+
+    #[test]
+    fn test_low_latency_does_not_wait() {
+        let settings = FlushSettings::new(200, true);
+        let (tx, rx) = mpsc::channel();
+        tx.send("a".to_string()).unwrap();
+        tx.send("b".to_string()).unwrap();
+        let sender = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            tx.send("c".to_string()).unwrap();
+        });
+
+        assert_eq!(next_batch(&rx, &settings).as_deref(), Some("ab"));
+        assert_eq!(next_batch(&rx, &settings).as_deref(), Some("c"));
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn test_validate_flush_interval() {
+        assert!(validate_flush_interval(0).is_ok());
+        assert!(validate_flush_interval(MAX_FLUSH_INTERVAL_MS).is_ok());
+        assert!(validate_flush_interval(MAX_FLUSH_INTERVAL_MS + 1).is_err());
+    }
+}
@@ -0,0 +1,78 @@
+//! Detection of multiplexer control modes. `tmux -CC` wraps its whole control
+//! protocol in one DCS sequence (`ESC P 1000 p` ... `ESC \`); the stream inside
+//! is meant for a control-mode client, not a terminal emulator, which would
+//! render it as garbage. GNU screen has no control mode.
+
+use serde::{Deserialize, Serialize};
+
+/// Protocol spoken while a program is in control mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ControlProtocol {
+    Tmux,
+}
+
+impl ControlProtocol {
+    /// The protocol started by a DCS header, if it starts one
+    pub fn from_dcs(params: &[u16], intermediates: &[u8], action: u8) -> Option<Self> {
+        (params == [1000] && intermediates.is_empty() && action == b'p')
+            .then_some(ControlProtocol::Tmux)
+    }
+
+    /// Command telling the program in control mode the client's size, which
+    /// it uses instead of the PTY's
+    pub fn resize_command(&self, cols: u16, rows: u16) -> String {
+        match self {
+            // tmux 3 also accepts `WxH`; the comma form works with tmux 2 too
+            ControlProtocol::Tmux => format!("refresh-client -C {},{}\n", cols, rows),
+        }
+    }
+}
+
+/// Payload of the `pty-control-mode` event, sent when a program enters
+/// control mode and again when it leaves it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PtyControlMode {
+    pub pty_id: String,
+    pub protocol: ControlProtocol,
+    pub active: bool,
+}
+
+/// Control mode entered or left within a chunk of output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlModeChange {
+    pub protocol: ControlProtocol,
+    pub active: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tmux_control_mode_header() {
+        assert_eq!(
+            ControlProtocol::from_dcs(&[1000], &[], b'p'),
+            Some(ControlProtocol::Tmux)
+        );
+        // DECRQSS and sixel headers are other DCS sequences
+        assert_eq!(ControlProtocol::from_dcs(&[], b"$", b'q'), None);
+        assert_eq!(ControlProtocol::from_dcs(&[0], &[], b'q'), None);
+    }
+
+    #[test]
+    fn test_tmux_resize_command() {
+        assert_eq!(
+            ControlProtocol::Tmux.resize_command(120, 40),
+            "refresh-client -C 120,40\n"
+        );
+    }
+
+    #[test]
+    fn test_protocol_serializes_lowercase() {
+        assert_eq!(
+            serde_json::to_value(ControlProtocol::Tmux).unwrap(),
+            serde_json::json!("tmux")
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! Best-effort cursor model kept for every session, read with
+//! `pty_cursor_position` and used to answer cursor position requests (DSR,
+//! `ESC [ 6 n`) for sessions that have no frontend terminal to answer them.
+//!
+//! Programs such as readline ask for the cursor position and wait for the
+//! reply, which hangs a non-interactive session. The model follows printable
+//! characters, CR/LF/BS/TAB, the common cursor movement sequences, save and
+//! restore (`ESC 7`/`ESC 8`, `CSI s`/`CSI u`), and returns home on a full
+//! reset (RIS) or a full screen clear (`CSI 2 J`, `CSI 3 J`). It is not a
+//! terminal emulator: wide characters, scroll regions, origin mode and the
+//! separate cursor of the alternate screen are not modeled, so the position
+//! is approximate after output that relies on them.
+
+/// Cursor position tracked from the output, 0-based
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorModel {
+    row: u16,
+    col: u16,
+    rows: u16,
+    cols: u16,
+    /// Position saved by DECSC / `CSI s`
+    saved: Option<(u16, u16)>,
+}
+
+impl CursorModel {
+    pub fn new(cols: u16, rows: u16) -> Self {
+        Self {
+            row: 0,
+            col: 0,
+            rows: rows.max(1),
+            cols: cols.max(1),
+            saved: None,
+        }
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.rows = rows.max(1);
+        self.cols = cols.max(1);
+        self.row = self.row.min(self.rows - 1);
+        self.col = self.col.min(self.cols - 1);
+    }
+
+    /// Advance over printed text, wrapping at the right margin
+    pub fn print(&mut self, bytes: &[u8]) {
+        // One column per character; UTF-8 continuation bytes don't count
+        for _ in bytes.iter().filter(|&&b| b & 0xc0 != 0x80) {
+            if self.col >= self.cols {
+                self.col = 0;
+                self.line_feed();
+            }
+            self.col += 1;
+        }
+    }
+
+    pub fn execute(&mut self, byte: u8) {
+        match byte {
+            b'\r' => self.col = 0,
+            b'\n' | 0x0b | 0x0c => self.line_feed(),
+            0x08 => self.col = self.col.min(self.cols - 1).saturating_sub(1),
+            b'\t' => self.col = ((self.col / 8 + 1) * 8).min(self.cols - 1),
+            _ => {}
+        }
+    }
+
+    /// Apply an escape sequence: RIS, DECSC/DECRC, IND, NEL and RI
+    pub fn esc(&mut self, byte: u8) {
+        match byte {
+            b'c' => *self = Self::new(self.cols, self.rows),
+            b'7' => self.save(),
+            b'8' => self.restore(),
+            b'D' => self.line_feed(),
+            b'E' => {
+                self.col = 0;
+                self.line_feed();
+            }
+            b'M' => self.row = self.row.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    /// Apply a cursor movement sequence (CUU/CUD/CUF/CUB/CNL/CPL/CHA/CUP/VPA),
+    /// save/restore, or a full screen clear
+    pub fn csi(&mut self, params: &[u16], action: u8) {
+        let n = params.first().copied().unwrap_or(0).max(1);
+        let (last_row, last_col) = (self.rows - 1, self.cols - 1);
+        match action {
+            b'A' => self.row = self.row.saturating_sub(n),
+            b'B' => self.row = self.row.saturating_add(n).min(last_row),
+            b'C' => self.col = self.col.saturating_add(n).min(last_col),
+            b'D' => self.col = self.col.min(last_col).saturating_sub(n),
+            b'E' => {
+                self.row = self.row.saturating_add(n).min(last_row);
+                self.col = 0;
+            }
+            b'F' => {
+                self.row = self.row.saturating_sub(n);
+                self.col = 0;
+            }
+            b'G' | b'`' => self.col = (n - 1).min(last_col),
+            b'd' => self.row = (n - 1).min(last_row),
+            b'H' | b'f' => {
+                self.row = (n - 1).min(last_row);
+                self.col = (params.get(1).copied().unwrap_or(0).max(1) - 1).min(last_col);
+            }
+            b'J' if matches!(params.first(), Some(2 | 3)) => {
+                self.row = 0;
+                self.col = 0;
+            }
+            b's' if params.is_empty() => self.save(),
+            b'u' => self.restore(),
+            _ => {}
+        }
+    }
+
+    /// 0-based (row, col)
+    pub fn position(&self) -> (u16, u16) {
+        (self.row, self.col.min(self.cols - 1))
+    }
+
+    /// Cursor position report (CPR), 1-based
+    pub fn report(&self) -> String {
+        let (row, col) = self.position();
+        format!("\x1b[{};{}R", row + 1, col + 1)
+    }
+
+    fn save(&mut self) {
+        self.saved = Some((self.row, self.col));
+    }
+
+    fn restore(&mut self) {
+        // Without a saved position the cursor goes home
+        let (row, col) = self.saved.unwrap_or((0, 0));
+        self.row = row.min(self.rows - 1);
+        self.col = col.min(self.cols);
+    }
+
+    fn line_feed(&mut self) {
+        // At the bottom the screen scrolls and the cursor stays on the last row
+        self.row = (self.row + 1).min(self.rows - 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_follows_text_and_movement() {
+        let mut cursor = CursorModel::new(80, 24);
+        assert_eq!(cursor.report(), "\x1b[1;1R");
+
+        cursor.print("héllo".as_bytes());
+        assert_eq!(cursor.report(), "\x1b[1;6R");
+        cursor.execute(b'\r');
+        cursor.execute(b'\n');
+        assert_eq!(cursor.report(), "\x1b[2;1R");
+
+        cursor.csi(&[10, 20], b'H');
+        assert_eq!(cursor.report(), "\x1b[10;20R");
+        cursor.csi(&[], b'A');
+        cursor.csi(&[5], b'D');
+        assert_eq!(cursor.report(), "\x1b[9;15R");
+    }
+
+    #[test]
+    fn test_cursor_wraps_and_stays_on_screen() {
+        let mut cursor = CursorModel::new(10, 3);
+        cursor.print(b"0123456789");
+        // Pending wrap: the cursor stays in the last column until the next character
+        assert_eq!(cursor.report(), "\x1b[1;10R");
+        cursor.print(b"a");
+        assert_eq!(cursor.report(), "\x1b[2;2R");
+
+        for _ in 0..5 {
+            cursor.execute(b'\n');
+        }
+        assert_eq!(cursor.report(), "\x1b[3;2R");
+        cursor.csi(&[100, 100], b'H');
+        assert_eq!(cursor.report(), "\x1b[3;10R");
+    }
+
+    #[test]
+    fn test_cursor_resets_and_restores() {
+        let mut cursor = CursorModel::new(80, 24);
+        cursor.csi(&[5, 10], b'H');
+        cursor.esc(b'7');
+        cursor.csi(&[20, 1], b'H');
+        cursor.esc(b'8');
+        assert_eq!(cursor.position(), (4, 9));
+
+        cursor.csi(&[], b's');
+        cursor.print(b"abc");
+        cursor.csi(&[], b'u');
+        assert_eq!(cursor.position(), (4, 9));
+
+        // Clearing below the cursor doesn't move it, clearing the screen does
+        cursor.csi(&[], b'J');
+        assert_eq!(cursor.position(), (4, 9));
+        cursor.csi(&[2], b'J');
+        assert_eq!(cursor.position(), (0, 0));
+
+        cursor.csi(&[5, 10], b'H');
+        cursor.esc(b'7');
+        cursor.esc(b'c');
+        assert_eq!(cursor.position(), (0, 0));
+        // RIS also forgets the saved position
+        cursor.esc(b'8');
+        assert_eq!(cursor.position(), (0, 0));
+    }
+}
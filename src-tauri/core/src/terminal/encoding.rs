@@ -0,0 +1,281 @@
+//! Output in a legacy encoding, e.g. GBK or Shift_JIS from an older program,
+//! decoded to UTF-8 before anything else sees it.
+//!
+//! Output is taken as UTF-8 unless the session is told otherwise. With the
+//! `detect_encoding` spawn option, the first output is sampled: once it holds
+//! enough non-ASCII text that is valid UTF-8, or stays ASCII for
+//! [`MAX_DETECT_BYTES`], it is taken as UTF-8; as soon as it isn't valid UTF-8,
+//! the most likely legacy encoding is guessed from what was sampled. Either way
+//! `pty-encoding-detected` is sent and detection stops.
+//!
+//! Detection is a guess and can be wrong, most often when the first non-UTF-8
+//! output is short or related encodings share byte ranges (e.g. GBK and Big5).
+//! The frontend corrects it with `pty_set_output_encoding`, which also stops
+//! detection. Output already passed on is not decoded again.
+
+use super::get_output_state;
+use chardetng::EncodingDetector;
+use encoding_rs::{Decoder, Encoding, UTF_8};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// Non-ASCII bytes of valid UTF-8 after which detection settles on UTF-8
+pub const DETECT_NON_ASCII_BYTES: usize = 64;
+
+/// Output after which detection settles on UTF-8 if nothing else was found
+pub const MAX_DETECT_BYTES: usize = 64 * 1024;
+
+/// Payload of the `pty-encoding-detected` event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PtyEncodingDetected {
+    pub pty_id: String,
+    /// Name of the encoding, e.g. `UTF-8`, `GBK` or `Shift_JIS`
+    pub encoding: String,
+}
+
+/// An output chunk decoded by [`OutputDecoder::decode`]
+#[derive(Debug)]
+pub struct Decoded<'a> {
+    /// The chunk as UTF-8, or as read while still taken as UTF-8
+    pub bytes: Cow<'a, [u8]>,
+    /// Set when detection settled on an encoding with this chunk
+    pub detected: Option<&'static Encoding>,
+}
+
+struct Detector {
+    guesser: EncodingDetector,
+    /// Start of a UTF-8 character cut off at the end of the last chunk
+    tail: Vec<u8>,
+    non_ascii: usize,
+    seen: usize,
+}
+
+impl Detector {
+    fn new() -> Self {
+        Self {
+            guesser: EncodingDetector::new(),
+            tail: Vec::new(),
+            non_ascii: 0,
+            seen: 0,
+        }
+    }
+
+    /// Sample a chunk. Returns the bytes to pass on, which hold back a
+    /// character cut off at the end so that it can still be decoded as a
+    /// legacy encoding, and the encoding once it is settled.
+    fn observe(&mut self, bytes: &[u8]) -> (Vec<u8>, Option<&'static Encoding>) {
+        self.guesser.feed(bytes, false);
+        self.seen += bytes.len();
+        let mut sample = std::mem::take(&mut self.tail);
+        sample.extend_from_slice(bytes);
+
+        let incomplete = match std::str::from_utf8(&sample) {
+            Ok(_) => 0,
+            Err(e) if e.error_len().is_none() => sample.len() - e.valid_up_to(),
+            Err(_) => return (sample, Some(self.guesser.guess(None, false))),
+        };
+        self.non_ascii += bytes.iter().filter(|byte| !byte.is_ascii()).count();
+        if self.non_ascii >= DETECT_NON_ASCII_BYTES || self.seen >= MAX_DETECT_BYTES {
+            return (sample, Some(UTF_8));
+        }
+        self.tail = sample.split_off(sample.len() - incomplete);
+        (sample, None)
+    }
+}
+
+/// Decodes a session's output to UTF-8, see the module docs
+pub struct OutputDecoder {
+    encoding: &'static Encoding,
+    /// `None` while the output is UTF-8, which is passed on as read
+    decoder: Option<Decoder>,
+    detector: Option<Detector>,
+    /// Whether the session was started with detection, kept for a respawn
+    detect: bool,
+}
+
+impl std::fmt::Debug for OutputDecoder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OutputDecoder")
+            .field("encoding", &self.encoding.name())
+            .field("detecting", &self.detector.is_some())
+            .finish()
+    }
+}
+
+impl OutputDecoder {
+    pub fn new(detect: bool) -> Self {
+        Self {
+            encoding: UTF_8,
+            decoder: None,
+            detector: detect.then(Detector::new),
+            detect,
+        }
+    }
+
+    /// Encoding the output is decoded from
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    /// Whether the session was started with `detect_encoding`
+    pub fn detects(&self) -> bool {
+        self.detect
+    }
+
+    /// Decode from `encoding` from now on and stop detecting
+    pub fn set_encoding(&mut self, encoding: &'static Encoding) {
+        self.detector = None;
+        self.switch(encoding);
+    }
+
+    fn switch(&mut self, encoding: &'static Encoding) {
+        self.encoding = encoding;
+        self.decoder = (encoding != UTF_8).then(|| encoding.new_decoder_without_bom_handling());
+    }
+
+    pub fn decode<'a>(&mut self, bytes: &'a [u8]) -> Decoded<'a> {
+        let mut detected = None;
+        let mut bytes = Cow::Borrowed(bytes);
+        if let Some(detector) = &mut self.detector {
+            let (sample, encoding) = detector.observe(&bytes);
+            bytes = Cow::Owned(sample);
+            if let Some(encoding) = encoding {
+                self.detector = None;
+                self.switch(encoding);
+                detected = Some(encoding);
+            }
+        }
+        if let Some(decoder) = &mut self.decoder {
+            bytes = Cow::Owned(decode_with(decoder, &bytes, false).into_bytes());
+        }
+        Decoded { bytes, detected }
+    }
+
+    /// Whatever is still held back, e.g. when the PTY closes: the start of a
+    /// character, replaced with U+FFFD if it was being decoded
+    pub fn finish(&mut self) -> Vec<u8> {
+        if let Some(detector) = &mut self.detector {
+            return std::mem::take(&mut detector.tail);
+        }
+        match &mut self.decoder {
+            Some(decoder) => {
+                let text = decode_with(decoder, &[], true);
+                // The decoder can't be used once it has been given the last input
+                self.switch(self.encoding);
+                text.into_bytes()
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+fn decode_with(decoder: &mut Decoder, bytes: &[u8], last: bool) -> String {
+    let capacity = decoder
+        .max_utf8_buffer_length(bytes.len())
+        .unwrap_or(bytes.len() * 3);
+    let mut text = String::with_capacity(capacity);
+    let _ = decoder.decode_to_string(bytes, &mut text, last);
+    text
+}
+
+/// The encoding for a label such as `gbk`, `cp936` or `shift_jis`. Encodings
+/// that don't keep ASCII as is, like UTF-16, are rejected since escape
+/// sequences couldn't be read from them.
+pub fn encoding_for_label(label: &str) -> Result<&'static Encoding, String> {
+    let encoding = Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| format!("Unknown encoding {}", label))?;
+    if !encoding.is_ascii_compatible() {
+        return Err(format!(
+            "Encoding {} can't be used for terminal output",
+            encoding.name()
+        ));
+    }
+    Ok(encoding)
+}
+
+/// Decode the session's output from `encoding` from now on, e.g. `gbk`, `big5`
+/// or `utf-8`, replacing a wrong guess of `detect_encoding` or setting one
+/// known in advance. Stops detection. Returns the encoding's canonical name.
+#[tauri::command]
+pub fn pty_set_output_encoding(pty_id: String, encoding: String) -> Result<String, String> {
+    let encoding = encoding_for_label(&encoding)?;
+    let output = get_output_state(&pty_id)?;
+    output.lock().unwrap().encoding.set_encoding(encoding);
+    info!("Decoding output of PTY {} as {}", pty_id, encoding.name());
+    Ok(encoding.name().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{GBK, SHIFT_JIS};
+
+    fn decode_all(decoder: &mut OutputDecoder, chunks: &[&[u8]]) -> String {
+        let mut out = Vec::new();
+        for chunk in chunks {
+            out.extend_from_slice(&decoder.decode(chunk).bytes);
+        }
+        out.extend(decoder.finish());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_utf8_is_passed_through_and_detected() {
+        let mut decoder = OutputDecoder::new(true);
+        let text = "文件已保存，没有发现错误。".repeat(4);
+        let bytes = text.as_bytes();
+        let (first, rest) = bytes.split_at(5);
+        let mut out = decoder.decode(first).bytes.into_owned();
+        let decoded = decoder.decode(rest);
+        assert_eq!(decoded.detected, Some(UTF_8));
+        out.extend_from_slice(&decoded.bytes);
+        assert_eq!(String::from_utf8(out).unwrap(), text);
+    }
+
+    #[test]
+    fn test_legacy_output_is_detected_and_decoded() {
+        let text =
+            "\x1b[31m错误：没有那个文件或目录。请检查路径是否正确，然后再试一次。\x1b[0m\r\n$ ";
+        let (bytes, _, _) = GBK.encode(text);
+        let mut decoder = OutputDecoder::new(true);
+        let (first, rest) = bytes.split_at(5);
+        let mut out = decoder.decode(first).bytes.into_owned();
+        let decoded = decoder.decode(rest);
+        assert_eq!(decoded.detected, Some(GBK));
+        out.extend_from_slice(&decoded.bytes);
+        assert_eq!(String::from_utf8(out).unwrap(), text);
+    }
+
+    #[test]
+    fn test_override_stops_detection() {
+        let (bytes, _, _) = SHIFT_JIS.encode("ファイルが見つかりません");
+        let mut decoder = OutputDecoder::new(true);
+        decoder.set_encoding(SHIFT_JIS);
+        let decoded = decoder.decode(&bytes);
+        assert!(decoded.detected.is_none());
+        assert_eq!(
+            std::str::from_utf8(&decoded.bytes).unwrap(),
+            "ファイルが見つかりません"
+        );
+    }
+
+    #[test]
+    fn test_cut_off_character_is_completed_or_replaced() {
+        let (bytes, _, _) = GBK.encode("中文");
+        let mut decoder = OutputDecoder::new(false);
+        decoder.set_encoding(GBK);
+        assert_eq!(
+            decode_all(&mut decoder, &[&bytes[..1], &bytes[1..]]),
+            "中文"
+        );
+        assert_eq!(decode_all(&mut decoder, &[&bytes[..3]]), "中\u{fffd}");
+    }
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(encoding_for_label(" cp936 ").unwrap(), GBK);
+        assert!(encoding_for_label("utf-16le").is_err());
+        assert!(encoding_for_label("klingon").is_err());
+    }
+}
@@ -0,0 +1,267 @@
+//! Global error signatures checked against the output of every session, so a
+//! dashboard of many build terminals can surface failures as they happen.
+//!
+//! Signatures are regexes matched against each complete line of stripped
+//! output. A hit emits `pty-error-detected`; further hits in the same session
+//! within [`ERROR_DEBOUNCE`] are counted and reported with the next event.
+
+use log::info;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// Most signatures that can be registered
+const MAX_SIGNATURES: usize = 32;
+
+/// Longest accepted signature pattern
+const MAX_PATTERN_LEN: usize = 512;
+
+/// Compiled size allowed per signature, rejecting patterns such as large
+/// bounded repetitions that would be slow to match against every line
+const REGEX_SIZE_LIMIT: usize = 256 * 1024;
+
+/// Lines longer than this are checked in pieces
+const MAX_LINE_BYTES: usize = 4096;
+
+/// Minimum interval between two `pty-error-detected` events of a session
+pub const ERROR_DEBOUNCE: Duration = Duration::from_secs(5);
+
+/// Payload of the `pty-error-detected` event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PtyErrorDetected {
+    pub pty_id: String,
+    /// Pattern of the signature that matched
+    pub signature: String,
+    /// Text the signature matched
+    #[serde(rename = "match")]
+    pub matched: String,
+    /// The whole line it matched in
+    pub line: String,
+    /// Hits suppressed by the debounce since the previous event
+    pub suppressed: u32,
+}
+
+#[derive(Debug)]
+struct Signature {
+    pattern: String,
+    regex: Regex,
+}
+
+lazy_static::lazy_static! {
+    static ref SIGNATURES: RwLock<Arc<Vec<Signature>>> = RwLock::new(Arc::new(Vec::new()));
+}
+
+fn compile(pattern: &str) -> Result<Signature, String> {
+    if pattern.is_empty() {
+        return Err("Error signature must not be empty".to_string());
+    }
+    if pattern.len() > MAX_PATTERN_LEN {
+        return Err(format!(
+            "Error signature is {} bytes long, at most {} are allowed",
+            pattern.len(),
+            MAX_PATTERN_LEN
+        ));
+    }
+    let regex = RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|e| format!("Invalid error signature {}: {}", pattern, e))?;
+    Ok(Signature {
+        pattern: pattern.to_string(),
+        regex,
+    })
+}
+
+/// Signatures in effect, shared with the read loops
+fn signatures() -> Arc<Vec<Signature>> {
+    SIGNATURES.read().unwrap().clone()
+}
+
+/// Scans the output of one session line by line
+#[derive(Debug, Default)]
+pub struct ErrorScanner {
+    /// Output after the last line break
+    partial: String,
+    last_emit: Option<Instant>,
+    suppressed: u32,
+}
+
+impl ErrorScanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check the complete lines in the next chunk of stripped output against the
+    /// registered signatures
+    pub fn scan(&mut self, pty_id: &str, text: &str) -> Vec<PtyErrorDetected> {
+        let signatures = signatures();
+        if signatures.is_empty() {
+            self.partial.clear();
+            return Vec::new();
+        }
+        self.scan_with(&signatures, Instant::now(), pty_id, text)
+    }
+
+    fn scan_with(
+        &mut self,
+        signatures: &[Signature],
+        now: Instant,
+        pty_id: &str,
+        text: &str,
+    ) -> Vec<PtyErrorDetected> {
+        let mut hits = Vec::new();
+        self.partial.push_str(text);
+        while let Some(line) = self.next_line() {
+            let Some((signature, found)) = signatures
+                .iter()
+                .find_map(|signature| Some((signature, signature.regex.find(&line)?)))
+            else {
+                continue;
+            };
+            let ready = self
+                .last_emit
+                .is_none_or(|last| now.duration_since(last) >= ERROR_DEBOUNCE);
+            if !ready {
+                self.suppressed = self.suppressed.saturating_add(1);
+                continue;
+            }
+            hits.push(PtyErrorDetected {
+                pty_id: pty_id.to_string(),
+                signature: signature.pattern.clone(),
+                matched: found.as_str().to_string(),
+                line: line.trim_end_matches('\r').to_string(),
+                suppressed: self.suppressed,
+            });
+            self.last_emit = Some(now);
+            self.suppressed = 0;
+        }
+        hits
+    }
+
+    /// Take the next complete line, or a piece of an overlong one
+    fn next_line(&mut self) -> Option<String> {
+        let end = match self.partial.find('\n') {
+            Some(newline) => newline + 1,
+            None if self.partial.len() > MAX_LINE_BYTES => {
+                let mut cut = MAX_LINE_BYTES;
+                while !self.partial.is_char_boundary(cut) {
+                    cut -= 1;
+                }
+                cut
+            }
+            None => return None,
+        };
+        let mut line: String = self.partial.drain(..end).collect();
+        if line.ends_with('\n') {
+            line.pop();
+        }
+        Some(line)
+    }
+}
+
+/// Replace the error signatures checked against the output of every session.
+/// Patterns are regexes; all of them are rejected if any is invalid or too
+/// complex. An empty list turns checking off.
+#[tauri::command]
+pub fn pty_set_error_signatures(patterns: Vec<String>) -> Result<(), String> {
+    if patterns.len() > MAX_SIGNATURES {
+        return Err(format!(
+            "{} error signatures given, at most {} are allowed",
+            patterns.len(),
+            MAX_SIGNATURES
+        ));
+    }
+    let compiled = patterns
+        .iter()
+        .map(|pattern| compile(pattern))
+        .collect::<Result<Vec<_>, _>>()?;
+    info!(
+        "Checking output against {} error signatures",
+        compiled.len()
+    );
+    *SIGNATURES.write().unwrap() = Arc::new(compiled);
+    Ok(())
+}
+
+/// The registered error signatures
+#[tauri::command]
+pub fn pty_get_error_signatures() -> Vec<String> {
+    signatures()
+        .iter()
+        .map(|signature| signature.pattern.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile_all(patterns: &[&str]) -> Vec<Signature> {
+        patterns
+            .iter()
+            .map(|pattern| compile(pattern).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_signatures_match_complete_lines() {
+        let signatures = compile_all(&[r"error\[E\d+\]", "FAILED"]);
+        let mut scanner = ErrorScanner::new();
+        let now = Instant::now();
+
+        assert!(scanner
+            .scan_with(&signatures, now, "a", "Compiling app\nerror[E03")
+            .is_empty());
+        let hits = scanner.scan_with(&signatures, now, "a", "08]: mismatched types\r\n");
+        assert_eq!(
+            hits,
+            vec![PtyErrorDetected {
+                pty_id: "a".to_string(),
+                signature: r"error\[E\d+\]".to_string(),
+                matched: "error[E0308]".to_string(),
+                line: "error[E0308]: mismatched types".to_string(),
+                suppressed: 0,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_hits_are_debounced() {
+        let signatures = compile_all(&["FAILED"]);
+        let mut scanner = ErrorScanner::new();
+        let start = Instant::now();
+
+        let hits = scanner.scan_with(&signatures, start, "a", "test a FAILED\ntest b FAILED\n");
+        assert_eq!(hits.len(), 1);
+        assert!(scanner
+            .scan_with(&signatures, start + Duration::from_secs(1), "a", "FAILED\n")
+            .is_empty());
+
+        let hits = scanner.scan_with(&signatures, start + ERROR_DEBOUNCE, "a", "FAILED\n");
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].suppressed, 2);
+    }
+
+    #[test]
+    fn test_overlong_lines_are_checked_in_pieces() {
+        let signatures = compile_all(&["panic"]);
+        let mut scanner = ErrorScanner::new();
+        let hits = scanner.scan_with(
+            &signatures,
+            Instant::now(),
+            "a",
+            &format!("{}panic", "x".repeat(MAX_LINE_BYTES)),
+        );
+        assert!(hits.is_empty());
+        assert_eq!(scanner.partial, "panic");
+    }
+
+    #[test]
+    fn test_invalid_and_complex_signatures_are_rejected() {
+        assert!(compile("").is_err());
+        assert!(compile("(unclosed").is_err());
+        assert!(compile(&"a".repeat(MAX_PATTERN_LEN + 1)).is_err());
+        assert!(compile(r"\w{1000}\w{1000}").is_err());
+    }
+}
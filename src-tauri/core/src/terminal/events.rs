@@ -0,0 +1,415 @@
+//! Every PTY event goes through [`emit`], which sends it to the frontend as a
+//! Tauri event and hands it to Rust subscribers registered with
+//! [`subscribe_pty_events`]. Embedders and headless consumers get all events
+//! through one callback without going through the JS event system.
+//!
+//! Sessions send their events to a [`PtyEventSink`] rather than to the app
+//! directly: the app's handle is the sink of sessions started by commands, and
+//! tests use a collector so that sessions can run without a webview.
+
+use super::bell::PtyBell;
+use super::control_mode::PtyControlMode;
+use super::encoding::PtyEncodingDetected;
+use super::error_signatures::PtyErrorDetected;
+use super::exit_hook::PtyExitHookRan;
+use super::groups::PtyGroupEvent;
+use super::hyperlink::PtyHyperlink;
+use super::init::PtySubsystemReady;
+use super::paste::PtyPasteWarning;
+use super::resize::PtyResizeRejected;
+use super::run::PtyCommandResult;
+use super::split::PtySplit;
+use super::title::PtyTitle;
+use super::workspace::PtyWorkspaceWarning;
+use super::write_queue::PtyWriteProgress;
+use super::{PtyOutput, PtyShellChanged};
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Emitter, Manager};
+
+/// Payload of the `pty-close` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PtyClose {
+    pub pty_id: String,
+    /// Black box dump written because the session crashed, to attach to a report
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crash_dump: Option<String>,
+}
+
+/// Payload of the `pty-cwd` event, sent when the shell reports a new working
+/// directory through OSC 7
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PtyCwd {
+    pub pty_id: String,
+    pub cwd: String,
+}
+
+/// All events emitted for sessions, with the payload of the matching Tauri event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
+pub enum PtyEvent {
+    Output(PtyOutput),
+    Close(PtyClose),
+    Title(PtyTitle),
+    Cwd(PtyCwd),
+    Bell(PtyBell),
+    ControlMode(PtyControlMode),
+    Hyperlink(PtyHyperlink),
+    CommandResult(PtyCommandResult),
+    ShellChanged(PtyShellChanged),
+    ResizeRejected(PtyResizeRejected),
+    GroupCreated(PtyGroupEvent),
+    GroupJoined(PtyGroupEvent),
+    GroupLeft(PtyGroupEvent),
+    GroupKilled(PtyGroupEvent),
+    WorkspaceWarning(PtyWorkspaceWarning),
+    WriteProgress(PtyWriteProgress),
+    PasteWarning(PtyPasteWarning),
+    ErrorDetected(PtyErrorDetected),
+    SubsystemReady(PtySubsystemReady),
+    Split(PtySplit),
+    EncodingDetected(PtyEncodingDetected),
+    ExitHookRan(PtyExitHookRan),
+}
+
+impl PtyEvent {
+    /// Name of the Tauri event
+    pub fn name(&self) -> &'static str {
+        match self {
+            PtyEvent::Output(_) => "pty-output",
+            PtyEvent::Close(_) => "pty-close",
+            PtyEvent::Title(_) => "pty-title",
+            PtyEvent::Cwd(_) => "pty-cwd",
+            PtyEvent::Bell(_) => "pty-bell",
+            PtyEvent::ControlMode(_) => "pty-control-mode",
+            PtyEvent::Hyperlink(_) => "pty-hyperlink",
+            PtyEvent::CommandResult(_) => "pty-command-result",
+            PtyEvent::ShellChanged(_) => "pty-shell-changed",
+            PtyEvent::ResizeRejected(_) => "pty-resize-rejected",
+            PtyEvent::GroupCreated(_) => "pty-group-created",
+            PtyEvent::GroupJoined(_) => "pty-group-joined",
+            PtyEvent::GroupLeft(_) => "pty-group-left",
+            PtyEvent::GroupKilled(_) => "pty-group-killed",
+            PtyEvent::WorkspaceWarning(_) => "pty-workspace-warning",
+            PtyEvent::WriteProgress(_) => "pty-write-progress",
+            PtyEvent::PasteWarning(_) => "pty-paste-warning",
+            PtyEvent::ErrorDetected(_) => "pty-error-detected",
+            PtyEvent::SubsystemReady(_) => "pty-subsystem-ready",
+            PtyEvent::Split(_) => "pty-split",
+            PtyEvent::EncodingDetected(_) => "pty-encoding-detected",
+            PtyEvent::ExitHookRan(_) => "pty-exit-hook-ran",
+        }
+    }
+
+    /// Sessions the event is about, none for app-wide events
+    pub fn pty_ids(&self) -> Vec<&str> {
+        match self {
+            PtyEvent::Output(payload) => vec![payload.pty_id.as_str()],
+            PtyEvent::Close(payload) => vec![payload.pty_id.as_str()],
+            PtyEvent::Title(payload) => vec![payload.pty_id.as_str()],
+            PtyEvent::Cwd(payload) => vec![payload.pty_id.as_str()],
+            PtyEvent::Bell(payload) => vec![payload.pty_id.as_str()],
+            PtyEvent::ControlMode(payload) => vec![payload.pty_id.as_str()],
+            PtyEvent::Hyperlink(payload) => vec![payload.pty_id.as_str()],
+            PtyEvent::CommandResult(payload) => vec![payload.pty_id.as_str()],
+            PtyEvent::ShellChanged(payload) => vec![payload.pty_id.as_str()],
+            PtyEvent::ResizeRejected(payload) => vec![payload.pty_id.as_str()],
+            PtyEvent::GroupCreated(payload)
+            | PtyEvent::GroupJoined(payload)
+            | PtyEvent::GroupLeft(payload)
+            | PtyEvent::GroupKilled(payload) => {
+                payload.pty_ids.iter().map(String::as_str).collect()
+            }
+            PtyEvent::WriteProgress(payload) => vec![payload.pty_id.as_str()],
+            PtyEvent::PasteWarning(payload) => vec![payload.pty_id.as_str()],
+            PtyEvent::ErrorDetected(payload) => vec![payload.pty_id.as_str()],
+            PtyEvent::Split(payload) => vec![payload.parent_id.as_str(), payload.child_id.as_str()],
+            PtyEvent::EncodingDetected(payload) => vec![payload.pty_id.as_str()],
+            // Sent after `pty-close`, when the session's counts are gone
+            PtyEvent::ExitHookRan(_) => Vec::new(),
+            PtyEvent::WorkspaceWarning(_) | PtyEvent::SubsystemReady(_) => Vec::new(),
+        }
+    }
+}
+
+/// Handle returned by [`subscribe_pty_events`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+type Subscriber = Arc<Mutex<Box<dyn Fn(PtyEvent) + Send>>>;
+
+static NEXT_SUBSCRIPTION: AtomicU64 = AtomicU64::new(1);
+
+lazy_static::lazy_static! {
+    static ref SUBSCRIBERS: Mutex<HashMap<SubscriptionId, Subscriber>> =
+        Mutex::new(HashMap::new());
+    /// Events emitted per session and event name, dropped with `pty-close`
+    static ref EVENT_COUNTS: Mutex<HashMap<String, HashMap<&'static str, u64>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Receive every PTY event in Rust. The callback runs on the thread emitting
+/// the event (e.g. a session's output thread), so it should return quickly.
+/// It may subscribe or unsubscribe, including itself.
+pub fn subscribe_pty_events(cb: impl Fn(PtyEvent) + Send + 'static) -> SubscriptionId {
+    let id = SubscriptionId(NEXT_SUBSCRIPTION.fetch_add(1, Ordering::Relaxed));
+    SUBSCRIBERS
+        .lock()
+        .unwrap()
+        .insert(id, Arc::new(Mutex::new(Box::new(cb))));
+    id
+}
+
+/// Remove a subscription. Returns false if it was already removed.
+pub fn unsubscribe(id: SubscriptionId) -> bool {
+    SUBSCRIBERS.lock().unwrap().remove(&id).is_some()
+}
+
+fn notify(event: &PtyEvent) {
+    // Call outside the registry lock so callbacks can (un)subscribe
+    let subscribers: Vec<Subscriber> = SUBSCRIBERS.lock().unwrap().values().cloned().collect();
+    for subscriber in subscribers {
+        let callback = subscriber.lock().unwrap();
+        callback(event.clone());
+    }
+}
+
+/// Where the events of a session go
+pub trait PtyEventSink: Send + Sync + 'static {
+    fn send(&self, event: PtyEvent);
+
+    /// Directory for black box dumps of crashed sessions, if there is one
+    fn log_dir(&self) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Sink shared by a session's threads
+pub type EventSink = Arc<dyn PtyEventSink>;
+
+/// The sink of sessions started through commands
+pub(super) fn sink(app: &AppHandle) -> EventSink {
+    Arc::new(app.clone())
+}
+
+/// Sends events to the frontend as Tauri events
+impl PtyEventSink for AppHandle {
+    fn send(&self, event: PtyEvent) {
+        let name = event.name();
+        let result = match event {
+            PtyEvent::Output(payload) => self.emit(name, payload),
+            PtyEvent::Close(payload) => self.emit(name, payload),
+            PtyEvent::Title(payload) => self.emit(name, payload),
+            PtyEvent::Cwd(payload) => self.emit(name, payload),
+            PtyEvent::Bell(payload) => self.emit(name, payload),
+            PtyEvent::ControlMode(payload) => self.emit(name, payload),
+            PtyEvent::Hyperlink(payload) => self.emit(name, payload),
+            PtyEvent::CommandResult(payload) => self.emit(name, payload),
+            PtyEvent::ShellChanged(payload) => self.emit(name, payload),
+            PtyEvent::ResizeRejected(payload) => self.emit(name, payload),
+            PtyEvent::GroupCreated(payload)
+            | PtyEvent::GroupJoined(payload)
+            | PtyEvent::GroupLeft(payload)
+            | PtyEvent::GroupKilled(payload) => self.emit(name, payload),
+            PtyEvent::WorkspaceWarning(payload) => self.emit(name, payload),
+            PtyEvent::WriteProgress(payload) => self.emit(name, payload),
+            PtyEvent::PasteWarning(payload) => self.emit(name, payload),
+            PtyEvent::ErrorDetected(payload) => self.emit(name, payload),
+            PtyEvent::SubsystemReady(payload) => self.emit(name, payload),
+            PtyEvent::Split(payload) => self.emit(name, payload),
+            PtyEvent::EncodingDetected(payload) => self.emit(name, payload),
+            PtyEvent::ExitHookRan(payload) => self.emit(name, payload),
+        };
+        if let Err(e) = result {
+            error!("Failed to emit {} event: {}", name, e);
+        }
+    }
+
+    fn log_dir(&self) -> Option<PathBuf> {
+        match self.path().app_log_dir() {
+            Ok(dir) => Some(dir),
+            Err(e) => {
+                error!("No app log directory: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Send an event to Rust subscribers and to the sink
+pub(super) fn emit(sink: &dyn PtyEventSink, event: PtyEvent) {
+    count(&event);
+    notify(&event);
+    sink.send(event);
+}
+
+fn count(event: &PtyEvent) {
+    let mut counts = EVENT_COUNTS.lock().unwrap();
+    if let PtyEvent::Close(payload) = event {
+        // The last event of a session
+        counts.remove(&payload.pty_id);
+        return;
+    }
+    for pty_id in event.pty_ids() {
+        // Output is counted often, so look up without allocating a key
+        if let Some(session) = counts.get_mut(pty_id) {
+            *session.entry(event.name()).or_default() += 1;
+        } else {
+            counts.insert(pty_id.to_string(), HashMap::from([(event.name(), 1)]));
+        }
+    }
+}
+
+/// Events emitted for a session so far, by event name
+pub(super) fn event_counts(pty_id: &str) -> HashMap<String, u64> {
+    EVENT_COUNTS
+        .lock()
+        .unwrap()
+        .get(pty_id)
+        .map(|counts| {
+            counts
+                .iter()
+                .map(|(name, count)| (name.to_string(), *count))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Total of [`event_counts`]
+pub(super) fn event_total(pty_id: &str) -> u64 {
+    EVENT_COUNTS
+        .lock()
+        .unwrap()
+        .get(pty_id)
+        .map_or(0, |counts| counts.values().sum())
+}
+
+/// Records events, for tests that run sessions without the app
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct CollectorSink {
+    events: Mutex<Vec<PtyEvent>>,
+    changed: std::sync::Condvar,
+}
+
+#[cfg(test)]
+impl CollectorSink {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn events(&self) -> Vec<PtyEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// Wait until an event matching `f` was received. Returns false on timeout.
+    pub fn wait_for(&self, timeout: std::time::Duration, f: impl Fn(&PtyEvent) -> bool) -> bool {
+        let events = self.events.lock().unwrap();
+        let (events, _) = self
+            .changed
+            .wait_timeout_while(events, timeout, |events| !events.iter().any(&f))
+            .unwrap();
+        events.iter().any(f)
+    }
+}
+
+#[cfg(test)]
+impl PtyEventSink for CollectorSink {
+    fn send(&self, event: PtyEvent) {
+        self.events.lock().unwrap().push(event);
+        self.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscribers_receive_events_until_unsubscribed() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let id = subscribe_pty_events(move |event| {
+            let _ = tx.send(event);
+        });
+
+        let close = PtyEvent::Close(PtyClose {
+            pty_id: "a".to_string(),
+            crash_dump: None,
+        });
+        notify(&close);
+        match rx.try_recv().unwrap() {
+            PtyEvent::Close(payload) => assert_eq!(payload.pty_id, "a"),
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        assert!(unsubscribe(id));
+        assert!(!unsubscribe(id));
+        notify(&close);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_collector_sink_records_events() {
+        let sink = CollectorSink::new();
+        let cwd = PtyEvent::Cwd(PtyCwd {
+            pty_id: "a".to_string(),
+            cwd: "/tmp".to_string(),
+        });
+        emit(sink.as_ref(), cwd);
+        assert!(sink.wait_for(std::time::Duration::ZERO, |event| {
+            matches!(event, PtyEvent::Cwd(payload) if payload.cwd == "/tmp")
+        }));
+        assert_eq!(sink.events().len(), 1);
+    }
+
+    #[test]
+    fn test_event_serializes_with_type_tag() {
+        let event = PtyEvent::Cwd(PtyCwd {
+            pty_id: "a".to_string(),
+            cwd: "/tmp".to_string(),
+        });
+        assert_eq!(event.name(), "pty-cwd");
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({ "type": "cwd", "payload": { "pty_id": "a", "cwd": "/tmp" } })
+        );
+    }
+
+    #[test]
+    fn test_events_are_counted_until_close() {
+        let sink = CollectorSink::new();
+        let bell = || {
+            PtyEvent::Bell(PtyBell {
+                pty_id: "counted".to_string(),
+                suppressed: 0,
+            })
+        };
+        emit(sink.as_ref(), bell());
+        emit(sink.as_ref(), bell());
+        emit(
+            sink.as_ref(),
+            PtyEvent::Title(PtyTitle {
+                pty_id: "counted".to_string(),
+                title: "vim".to_string(),
+            }),
+        );
+        assert_eq!(
+            event_counts("counted"),
+            HashMap::from([("pty-bell".to_string(), 2), ("pty-title".to_string(), 1)])
+        );
+        assert_eq!(event_total("counted"), 3);
+
+        emit(
+            sink.as_ref(),
+            PtyEvent::Close(PtyClose {
+                pty_id: "counted".to_string(),
+                crash_dump: None,
+            }),
+        );
+        assert!(event_counts("counted").is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+//! A program run on the host when a session's shell exits, e.g. to send a
+//! desktop notification or log that a long job finished, without the frontend
+//! waiting for `pty-close`.
+//!
+//! The program set with the `on_exit_command` spawn option is started directly,
+//! never through a shell, so nothing in the session's details can inject
+//! commands. It gets them both as `key=value` arguments, which can't be taken
+//! for options, and in the environment:
+//!
+//! | argument        | variable             |
+//! |-----------------|----------------------|
+//! | `pty_id=...`    | `TALKCODY_PTY_ID`    |
+//! | `exit_code=...` | `TALKCODY_EXIT_CODE` |
+//! | `cwd=...`       | `TALKCODY_CWD`       |
+//!
+//! `exit_code` and `cwd` are empty when unknown. The working directory is the
+//! one the shell last reported, which a program can set to anything, so control
+//! characters are removed from all values. The hook runs in the background
+//! after the session is cleaned up and `pty-exit-hook-ran` is sent once it
+//! finishes. It doesn't run for sessions ended with `pty_kill`.
+
+use super::events::{self, EventSink, PtyEvent};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::process::{Command, Stdio};
+
+/// Payload of the `pty-exit-hook-ran` event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PtyExitHookRan {
+    pub pty_id: String,
+    pub command: String,
+    /// Exit code of the hook, `None` if it couldn't be started or was killed
+    pub exit_code: Option<i32>,
+    /// Why the hook couldn't be started
+    pub error: Option<String>,
+}
+
+/// Details of an exited session passed to its hook
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitDetails {
+    pub pty_id: String,
+    pub exit_code: Option<i32>,
+    pub cwd: Option<String>,
+}
+
+impl ExitDetails {
+    /// (argument name, variable, sanitized value) of each detail
+    fn fields(&self) -> [(&'static str, &'static str, String); 3] {
+        [
+            ("pty_id", "TALKCODY_PTY_ID", sanitize(&self.pty_id)),
+            (
+                "exit_code",
+                "TALKCODY_EXIT_CODE",
+                self.exit_code
+                    .map(|code| code.to_string())
+                    .unwrap_or_default(),
+            ),
+            (
+                "cwd",
+                "TALKCODY_CWD",
+                self.cwd.as_deref().map(sanitize).unwrap_or_default(),
+            ),
+        ]
+    }
+
+    fn command(&self, program: &str) -> Command {
+        let mut command = Command::new(program);
+        for (arg, var, value) in self.fields() {
+            command.arg(format!("{}={}", arg, value));
+            command.env(var, value);
+        }
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        command
+    }
+}
+
+/// Drop control characters, including NUL and line breaks
+fn sanitize(value: &str) -> String {
+    value.chars().filter(|c| !c.is_control()).collect()
+}
+
+/// Start `program` for the exited session in the background, see the module docs
+pub(super) fn run(sink: &EventSink, program: String, details: ExitDetails) {
+    let sink = sink.clone();
+    std::thread::spawn(move || {
+        info!("Running exit hook {} for PTY {}", program, details.pty_id);
+        let result = details
+            .command(&program)
+            .status()
+            .map_err(|e| e.to_string());
+        if let Err(e) = &result {
+            warn!(
+                "Exit hook {} of PTY {} failed: {}",
+                program, details.pty_id, e
+            );
+        }
+        events::emit(
+            sink.as_ref(),
+            PtyEvent::ExitHookRan(PtyExitHookRan {
+                pty_id: details.pty_id,
+                command: program,
+                exit_code: result.as_ref().ok().and_then(|status| status.code()),
+                error: result.err(),
+            }),
+        );
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::events::CollectorSink;
+    use std::ffi::OsStr;
+    use std::time::Duration;
+
+    fn details(cwd: &str) -> ExitDetails {
+        ExitDetails {
+            pty_id: "a".to_string(),
+            exit_code: Some(2),
+            cwd: Some(cwd.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_details_are_passed_as_arguments_and_env() {
+        let command = details("/tmp/$(rm -rf ~)\n--force").command("notify");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["pty_id=a", "exit_code=2", "cwd=/tmp/$(rm -rf ~)--force"]
+        );
+        assert!(command
+            .get_envs()
+            .any(|(var, value)| var == "TALKCODY_EXIT_CODE" && value == Some(OsStr::new("2"))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hook_runs_and_reports() {
+        let sink = CollectorSink::new();
+        let sink_dyn: EventSink = sink.clone();
+        run(&sink_dyn, "false".to_string(), details("/tmp"));
+        run(&sink_dyn, "/no/such/hook".to_string(), details("/tmp"));
+
+        let ran = |command: &str, exit_code: Option<i32>, failed: bool| {
+            sink.wait_for(Duration::from_secs(10), |event| {
+                matches!(event, PtyEvent::ExitHookRan(ran)
+                    if ran.command == command
+                        && ran.exit_code == exit_code
+                        && ran.error.is_some() == failed)
+            })
+        };
+        assert!(ran("false", Some(1), false));
+        assert!(ran("/no/such/hook", None, true));
+    }
+}
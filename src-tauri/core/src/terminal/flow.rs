@@ -0,0 +1,147 @@
+//! Opt-in ack-based flow control for `pty-output`.
+//!
+//! Every output event carries a `seq`: the total number of bytes emitted so far,
+//! including that event. The frontend acknowledges with `pty_ack(pty_id, seq)`,
+//! and the read loop stops reading once a full window of output is unacknowledged.
+//! The program then blocks on its writes instead of the backend buffering
+//! without bound, and no output is ever dropped.
+
+use std::sync::{Condvar, Mutex};
+
+/// Default amount of unacknowledged output before reading pauses
+pub const DEFAULT_FLOW_WINDOW_BYTES: u64 = 256 * 1024;
+
+#[derive(Debug, Default)]
+struct FlowState {
+    /// Bytes handed to the emitter
+    queued: u64,
+    /// Bytes emitted to the frontend, i.e. the last seq
+    emitted: u64,
+    /// Bytes acknowledged by the frontend
+    acked: u64,
+    /// Set when the session goes away so a paused read loop can finish
+    closed: bool,
+}
+
+#[derive(Debug)]
+pub struct FlowControl {
+    window: u64,
+    state: Mutex<FlowState>,
+    window_open: Condvar,
+}
+
+impl FlowControl {
+    pub fn new(window: u64) -> Self {
+        Self {
+            window: window.max(1),
+            state: Mutex::new(FlowState::default()),
+            window_open: Condvar::new(),
+        }
+    }
+
+    /// Block the read loop while a full window is unacknowledged
+    pub fn wait_for_window(&self) {
+        let state = self.state.lock().unwrap();
+        let _state = self
+            .window_open
+            .wait_while(state, |state| {
+                !state.closed && state.queued - state.acked >= self.window
+            })
+            .unwrap();
+    }
+
+    /// Record output handed to the emitter
+    pub fn queue(&self, bytes: usize) {
+        self.state.lock().unwrap().queued += bytes as u64;
+    }
+
+    /// Record an emitted batch and return its seq
+    pub fn emit(&self, bytes: usize) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.emitted += bytes as u64;
+        state.emitted
+    }
+
+    /// Acknowledge everything up to `seq`. Stale acks are ignored.
+    pub fn ack(&self, seq: u64) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        if seq > state.emitted {
+            return Err(format!(
+                "Ack {} is past the last emitted seq {}",
+                seq, state.emitted
+            ));
+        }
+        if seq > state.acked {
+            state.acked = seq;
+            self.window_open.notify_all();
+        }
+        Ok(())
+    }
+
+    /// Output emitted but not yet acknowledged
+    pub fn unacked(&self) -> u64 {
+        let state = self.state.lock().unwrap();
+        state.emitted - state.acked
+    }
+
+    pub fn window(&self) -> u64 {
+        self.window
+    }
+
+    /// Release a paused read loop for good
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.window_open.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_reading_pauses_until_acked() {
+        let flow = Arc::new(FlowControl::new(10));
+        flow.queue(10);
+        let seq = flow.emit(10);
+        assert_eq!(seq, 10);
+        assert_eq!(flow.unacked(), 10);
+
+        let reader = {
+            let flow = flow.clone();
+            std::thread::spawn(move || flow.wait_for_window())
+        };
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!reader.is_finished());
+
+        flow.ack(seq).unwrap();
+        reader.join().unwrap();
+        assert_eq!(flow.unacked(), 0);
+    }
+
+    #[test]
+    fn test_ack_past_emitted_is_rejected() {
+        let flow = FlowControl::new(DEFAULT_FLOW_WINDOW_BYTES);
+        flow.queue(5);
+        flow.emit(5);
+        assert!(flow.ack(6).is_err());
+        assert!(flow.ack(5).is_ok());
+        // Stale acks are fine
+        assert!(flow.ack(3).is_ok());
+        assert_eq!(flow.unacked(), 0);
+    }
+
+    #[test]
+    fn test_close_releases_paused_reader() {
+        let flow = Arc::new(FlowControl::new(1));
+        flow.queue(1);
+        let reader = {
+            let flow = flow.clone();
+            std::thread::spawn(move || flow.wait_for_window())
+        };
+        flow.close();
+        reader.join().unwrap();
+    }
+}
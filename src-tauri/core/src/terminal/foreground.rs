@@ -0,0 +1,136 @@
+//! Switching the session a user looks at to the most responsive output
+//! settings, and optionally the other sessions to cheaper ones, in one call.
+//!
+//! The profiles only combine the per-session controls: the flush interval,
+//! low-latency mode and whether `pty-output` is emitted. Each can still be
+//! changed on its own afterwards.
+
+use super::coalesce::FlushSettings;
+use super::output::OutputState;
+use super::PTY_SESSIONS;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+/// Flush interval of background sessions in the `throughput` profile
+pub const BACKGROUND_FLUSH_INTERVAL_MS: u64 = 50;
+
+/// Settings `pty_set_foreground` applies to the sessions not in the foreground
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackgroundProfile {
+    /// Batch output over a longer interval, for fewer and larger events
+    Throughput,
+    /// Stop `pty-output` events, as with `pty_set_emit_enabled`
+    Detached,
+}
+
+/// Emit output as soon as it is read. Returns the scrollback to repaint from if
+/// output events were off, as `pty_reattach` does.
+fn apply_foreground(flush: &FlushSettings, output: &Mutex<OutputState>) -> Option<Vec<u8>> {
+    flush.set_low_latency(true);
+    let mut output = output.lock().unwrap();
+    if output.emit_output {
+        return None;
+    }
+    output.emit_output = true;
+    Some(output.scrollback.contents())
+}
+
+fn apply_background(
+    profile: BackgroundProfile,
+    flush: &FlushSettings,
+    output: &Mutex<OutputState>,
+) {
+    match profile {
+        BackgroundProfile::Throughput => {
+            flush.set_low_latency(false);
+            flush.set_interval_ms(flush.interval_ms().max(BACKGROUND_FLUSH_INTERVAL_MS));
+        }
+        BackgroundProfile::Detached => output.lock().unwrap().emit_output = false,
+    }
+}
+
+/// Give a session low-latency output with events enabled, e.g. when its tab is
+/// selected, and apply `background` to every other session if given. Returns
+/// the scrollback to repaint from if the session's output events were off.
+#[tauri::command]
+pub fn pty_set_foreground(
+    pty_id: String,
+    background: Option<BackgroundProfile>,
+) -> Result<Option<String>, String> {
+    // Collected first so no output lock is taken under the registry lock
+    type Controls = (Arc<FlushSettings>, Arc<Mutex<OutputState>>);
+    let (foreground, others): (Controls, Vec<Controls>) = {
+        let sessions = PTY_SESSIONS.lock().unwrap();
+        let session = sessions
+            .get(&pty_id)
+            .ok_or_else(|| format!("PTY session {} not found", pty_id))?;
+        let others = match background {
+            Some(_) => sessions
+                .iter()
+                .filter(|(id, _)| **id != pty_id)
+                .map(|(_, session)| (session.flush.clone(), session.output.clone()))
+                .collect(),
+            None => Vec::new(),
+        };
+        ((session.flush.clone(), session.output.clone()), others)
+    };
+
+    let contents = apply_foreground(&foreground.0, &foreground.1);
+    if let Some(profile) = background {
+        for (flush, output) in &others {
+            apply_background(profile, flush, output);
+        }
+    }
+    info!(
+        "Moved PTY {} to the foreground{}",
+        pty_id,
+        match background {
+            Some(profile) => format!(", {} others to {:?}", others.len(), profile),
+            None => String::new(),
+        }
+    );
+    Ok(contents.map(|contents| String::from_utf8_lossy(&contents).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::PtySpawnOptions;
+
+    #[test]
+    fn test_foreground_enables_output_and_low_latency() {
+        let flush = FlushSettings::new(BACKGROUND_FLUSH_INTERVAL_MS, false);
+        let output = Mutex::new(OutputState::new(&PtySpawnOptions::default()));
+        output.lock().unwrap().scrollback.append(b"$ ls\r\n");
+
+        assert_eq!(apply_foreground(&flush, &output), None);
+        assert!(flush.low_latency());
+
+        output.lock().unwrap().emit_output = false;
+        assert_eq!(
+            apply_foreground(&flush, &output),
+            Some(b"$ ls\r\n".to_vec())
+        );
+        assert!(output.lock().unwrap().emit_output);
+    }
+
+    #[test]
+    fn test_background_profiles() {
+        let flush = FlushSettings::new(5, true);
+        let output = Mutex::new(OutputState::new(&PtySpawnOptions::default()));
+        apply_background(BackgroundProfile::Throughput, &flush, &output);
+        assert!(!flush.low_latency());
+        assert_eq!(flush.interval_ms(), BACKGROUND_FLUSH_INTERVAL_MS);
+        assert!(output.lock().unwrap().emit_output);
+
+        // A longer configured interval is kept
+        flush.set_interval_ms(200);
+        apply_background(BackgroundProfile::Throughput, &flush, &output);
+        assert_eq!(flush.interval_ms(), 200);
+
+        apply_background(BackgroundProfile::Detached, &flush, &output);
+        assert!(!output.lock().unwrap().emit_output);
+    }
+}
@@ -0,0 +1,88 @@
+//! Environment variables given to every new session, e.g. an app-provided
+//! `EDITOR` or a telemetry opt-out, without passing them to each spawn.
+//!
+//! A shell's environment is layered, later layers winning:
+//! 1. the app's own environment, inherited
+//! 2. the global overlay set with `pty_set_global_env`
+//! 3. the `env` spawn option, where the variables of a profile belong
+//! 4. `TERM` and `COLORTERM`, which the terminal always sets
+//!
+//! The overlay applies when a shell starts, including after
+//! `pty_change_shell`; running shells keep the environment they have. It is
+//! kept in memory only, so the frontend sets it again on startup.
+
+use super::validate::check_env_names;
+use log::info;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+lazy_static::lazy_static! {
+    static ref GLOBAL_ENV: RwLock<HashMap<String, String>> = RwLock::new(HashMap::new());
+}
+
+/// Variables for a new shell: the overlay with the spawn's own `env` on top
+pub(super) fn with_overlay(env: &HashMap<String, String>) -> HashMap<String, String> {
+    overlay(&GLOBAL_ENV.read().unwrap(), env)
+}
+
+fn overlay(
+    global: &HashMap<String, String>,
+    env: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    let mut merged = global.clone();
+    merged.extend(env.iter().map(|(key, value)| (key.clone(), value.clone())));
+    merged
+}
+
+/// Replace the variables set for every new session, beneath the `env` of each
+/// spawn. See the module docs for the full layering.
+#[tauri::command]
+pub fn pty_set_global_env(env: HashMap<String, String>) -> Result<(), String> {
+    check_env_names(&env)?;
+    info!(
+        "Setting {} environment variables for new sessions",
+        env.len()
+    );
+    *GLOBAL_ENV.write().unwrap() = env;
+    Ok(())
+}
+
+/// The variables set with `pty_set_global_env`
+#[tauri::command]
+pub fn pty_get_global_env() -> HashMap<String, String> {
+    GLOBAL_ENV.read().unwrap().clone()
+}
+
+/// Stop setting variables for every new session
+#[tauri::command]
+pub fn pty_clear_global_env() {
+    GLOBAL_ENV.write().unwrap().clear();
+    info!("Cleared environment variables for new sessions");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spawn_env_wins_over_overlay() {
+        let global = HashMap::from([
+            ("EDITOR".to_string(), "talkcody --wait".to_string()),
+            ("DO_NOT_TRACK".to_string(), "1".to_string()),
+        ]);
+        let env = HashMap::from([("EDITOR".to_string(), "vim".to_string())]);
+        assert_eq!(
+            overlay(&global, &env),
+            HashMap::from([
+                ("EDITOR".to_string(), "vim".to_string()),
+                ("DO_NOT_TRACK".to_string(), "1".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_invalid_names_are_rejected() {
+        let env = HashMap::from([("A=B".to_string(), String::new())]);
+        assert!(pty_set_global_env(env).is_err());
+    }
+}
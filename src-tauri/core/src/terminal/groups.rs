@@ -0,0 +1,125 @@
+//! Logical groups of related sessions ("workspaces") over the session registry.
+
+use super::closures::CloseReason;
+use super::events::{self, PtyEvent, PtyEventSink};
+use super::{record_closure, PtySession, PTY_SESSIONS};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Mutex;
+use tauri::AppHandle;
+
+lazy_static::lazy_static! {
+    static ref PTY_GROUPS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// Payload of the `pty-group-*` lifecycle events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PtyGroupEvent {
+    pub group: String,
+    pub pty_ids: Vec<String>,
+}
+
+pub(super) fn ensure_group_exists(group: &str) -> Result<(), String> {
+    if PTY_GROUPS.lock().unwrap().contains(group) {
+        Ok(())
+    } else {
+        Err(format!("PTY group {} not found", group))
+    }
+}
+
+pub(super) fn emit_group_event(
+    sink: &dyn PtyEventSink,
+    event: fn(PtyGroupEvent) -> PtyEvent,
+    group: &str,
+    pty_ids: Vec<String>,
+) {
+    events::emit(
+        sink,
+        event(PtyGroupEvent {
+            group: group.to_string(),
+            pty_ids,
+        }),
+    );
+}
+
+/// Create a group unless it already exists, e.g. when restoring a workspace
+pub(super) fn ensure_group(sink: &dyn PtyEventSink, group: &str) {
+    if PTY_GROUPS.lock().unwrap().insert(group.to_string()) {
+        info!("Created PTY group {}", group);
+        emit_group_event(sink, PtyEvent::GroupCreated, group, Vec::new());
+    }
+}
+
+/// Emit `pty-group-left` for a grouped session that was removed from the registry
+pub(super) fn emit_group_left(sink: &dyn PtyEventSink, pty_id: &str, session: &PtySession) {
+    if let Some(group) = &session.group {
+        emit_group_event(sink, PtyEvent::GroupLeft, group, vec![pty_id.to_string()]);
+    }
+}
+
+/// Create a named group. Sessions join it by passing `group` in the spawn options.
+#[tauri::command]
+pub fn pty_spawn_group(app: AppHandle, name: String) -> Result<(), String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("PTY group name must not be empty".to_string());
+    }
+
+    if !PTY_GROUPS.lock().unwrap().insert(name.clone()) {
+        return Err(format!("PTY group {} already exists", name));
+    }
+
+    info!("Created PTY group {}", name);
+    emit_group_event(&app, PtyEvent::GroupCreated, &name, Vec::new());
+    Ok(())
+}
+
+/// List the ids of the live sessions in a group
+#[tauri::command]
+pub fn pty_list_group(name: String) -> Result<Vec<String>, String> {
+    ensure_group_exists(&name)?;
+
+    let sessions = PTY_SESSIONS.lock().unwrap();
+    let mut pty_ids: Vec<String> = sessions
+        .iter()
+        .filter(|(_, session)| session.group.as_deref() == Some(name.as_str()))
+        .map(|(pty_id, _)| pty_id.clone())
+        .collect();
+    pty_ids.sort();
+    Ok(pty_ids)
+}
+
+/// Kill every session in a group and remove the group. Returns the killed session ids.
+#[tauri::command]
+pub fn pty_kill_group(app: AppHandle, name: String) -> Result<Vec<String>, String> {
+    if !PTY_GROUPS.lock().unwrap().remove(&name) {
+        return Err(format!("PTY group {} not found", name));
+    }
+
+    let mut killed = Vec::new();
+    {
+        let mut sessions = PTY_SESSIONS.lock().unwrap();
+        let pty_ids: Vec<String> = sessions
+            .iter()
+            .filter(|(_, session)| session.group.as_deref() == Some(name.as_str()))
+            .map(|(pty_id, _)| pty_id.clone())
+            .collect();
+
+        for pty_id in pty_ids {
+            if let Some(mut session) = sessions.remove(&pty_id) {
+                if let Err(e) = session.child.kill() {
+                    // The process may have already exited
+                    warn!("Failed to kill PTY child process {}: {}", pty_id, e);
+                }
+                record_closure(&pty_id, &session, None, CloseReason::Killed, None);
+                killed.push(pty_id);
+            }
+        }
+    }
+    killed.sort();
+
+    info!("Killed PTY group {} ({} sessions)", name, killed.len());
+    emit_group_event(&app, PtyEvent::GroupKilled, &name, killed.clone());
+    Ok(killed)
+}
@@ -0,0 +1,113 @@
+//! Whether a session's program seems hung, for offering to kill it.
+//!
+//! A program is considered hung when it is still running, input for it is
+//! backed up and it has printed nothing for a while. Input alone isn't enough,
+//! since a busy program may take a while to read it, and silence alone isn't
+//! either, since a program waiting for input prints nothing. Input counts as
+//! backed up when at least `min_pending_bytes` wait in the write queue, or on
+//! Unix when the PTY takes no more input because the program isn't reading it.
+//! A session nobody typed into is therefore never reported hung.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Default time without output after which a session with backed up input is
+/// considered hung
+pub const DEFAULT_HANG_IDLE_MS: u64 = 10_000;
+
+/// When `pty_detect_hang` reports a session as hung
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HangThresholds {
+    /// Time without output
+    pub idle_ms: u64,
+    /// Queued input that counts as backed up
+    pub min_pending_bytes: u64,
+}
+
+impl Default for HangThresholds {
+    fn default() -> Self {
+        Self {
+            idle_ms: DEFAULT_HANG_IDLE_MS,
+            min_pending_bytes: 1,
+        }
+    }
+}
+
+/// Result of `pty_detect_hang`, with the signals it is based on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HangStatus {
+    pub pty_id: String,
+    pub hung: bool,
+    /// Whether the program is still running
+    pub alive: bool,
+    /// Input waiting in the write queue
+    pub pending_input_bytes: u64,
+    /// Whether the PTY takes no more input right now (Unix only)
+    pub input_blocked: bool,
+    /// Time since the last output, `None` if there was none yet
+    pub idle_ms: Option<u64>,
+}
+
+impl HangStatus {
+    /// Combine the signals. No output at all counts as idle for any threshold.
+    pub fn assess(
+        pty_id: &str,
+        alive: bool,
+        pending_input_bytes: u64,
+        input_blocked: bool,
+        idle: Option<Duration>,
+        thresholds: &HangThresholds,
+    ) -> Self {
+        let backed_up = input_blocked || pending_input_bytes >= thresholds.min_pending_bytes.max(1);
+        let silent = idle.is_none_or(|idle| idle >= Duration::from_millis(thresholds.idle_ms));
+        Self {
+            pty_id: pty_id.to_string(),
+            hung: alive && backed_up && silent,
+            alive,
+            pending_input_bytes,
+            input_blocked,
+            idle_ms: idle.map(|idle| idle.as_millis() as u64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assess(alive: bool, pending: u64, blocked: bool, idle_ms: Option<u64>) -> bool {
+        HangStatus::assess(
+            "a",
+            alive,
+            pending,
+            blocked,
+            idle_ms.map(Duration::from_millis),
+            &HangThresholds {
+                idle_ms: 1000,
+                min_pending_bytes: 64,
+            },
+        )
+        .hung
+    }
+
+    #[test]
+    fn test_hung_needs_backed_up_input_silence_and_a_live_child() {
+        assert!(assess(true, 100, false, Some(1500)));
+        assert!(assess(true, 0, true, None));
+        // Still printing
+        assert!(!assess(true, 100, true, Some(200)));
+        // Nothing to read, e.g. waiting at a prompt
+        assert!(!assess(true, 0, false, Some(60_000)));
+        // Less queued than the threshold
+        assert!(!assess(true, 10, false, Some(1500)));
+        assert!(!assess(false, 100, true, Some(1500)));
+    }
+
+    #[test]
+    fn test_thresholds_default_missing_fields() {
+        let thresholds: HangThresholds = serde_json::from_str(r#"{"idle_ms": 500}"#).unwrap();
+        assert_eq!(thresholds.idle_ms, 500);
+        assert_eq!(thresholds.min_pending_bytes, 1);
+    }
+}
@@ -0,0 +1,108 @@
+//! Hyperlinks emitted by programs with OSC 8, e.g. `ls --hyperlink`:
+//! `ESC ]8;params;URL ST text ESC ]8;; ST`.
+
+use serde::{Deserialize, Serialize};
+
+/// Longest link text kept; longer text is cut off
+const MAX_LINK_TEXT_BYTES: usize = 4096;
+
+/// Payload of the `pty-hyperlink` event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PtyHyperlink {
+    pub pty_id: String,
+    pub url: String,
+    /// The `id=` parameter. Runs of text with the same id belong to one link,
+    /// e.g. a link wrapped over several lines.
+    pub id: Option<String>,
+    pub text: String,
+}
+
+/// A complete link and the text it covered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hyperlink {
+    pub url: String,
+    pub id: Option<String>,
+    pub text: String,
+}
+
+/// Parsed OSC 8 sequence: `Some` opens a link, `None` closes the current one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkStart {
+    pub url: String,
+    pub id: Option<String>,
+}
+
+/// Parse the payload of an OSC sequence. Returns `None` for anything but OSC 8.
+pub fn parse_osc8(osc: &[u8]) -> Option<Option<LinkStart>> {
+    let rest = osc.strip_prefix(b"8;")?;
+    let separator = rest.iter().position(|&b| b == b';')?;
+    let (params, url) = (&rest[..separator], &rest[separator + 1..]);
+    if url.is_empty() {
+        return Some(None);
+    }
+
+    // Parameters are colon-separated key=value pairs; only id is defined
+    let id = params
+        .split(|&b| b == b':')
+        .find_map(|param| param.strip_prefix(b"id="))
+        .filter(|id| !id.is_empty())
+        .map(|id| String::from_utf8_lossy(id).to_string());
+    Some(Some(LinkStart {
+        url: String::from_utf8_lossy(url).to_string(),
+        id,
+    }))
+}
+
+/// Link whose text is still being printed
+#[derive(Debug)]
+pub struct OpenLink {
+    start: LinkStart,
+    text: Vec<u8>,
+}
+
+impl OpenLink {
+    pub fn new(start: LinkStart) -> Self {
+        Self {
+            start,
+            text: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        let room = MAX_LINK_TEXT_BYTES.saturating_sub(self.text.len());
+        self.text.extend_from_slice(&bytes[..bytes.len().min(room)]);
+    }
+
+    pub fn finish(self) -> Hyperlink {
+        Hyperlink {
+            url: self.start.url,
+            id: self.start.id,
+            text: String::from_utf8_lossy(&self.text).to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_osc8() {
+        assert_eq!(
+            parse_osc8(b"8;;https://example.com"),
+            Some(Some(LinkStart {
+                url: "https://example.com".to_string(),
+                id: None,
+            }))
+        );
+        assert_eq!(
+            parse_osc8(b"8;foo=bar:id=42;file:///tmp/a.txt"),
+            Some(Some(LinkStart {
+                url: "file:///tmp/a.txt".to_string(),
+                id: Some("42".to_string()),
+            }))
+        );
+        assert_eq!(parse_osc8(b"8;;"), Some(None));
+        assert_eq!(parse_osc8(b"7;file:///tmp"), None);
+    }
+}
@@ -0,0 +1,87 @@
+//! One-time setup of the terminal subsystem at app start, announced with
+//! `pty-subsystem-ready` so the frontend can wait for it before offering a
+//! new terminal.
+//!
+//! Setup warms the shell detection cache, which otherwise makes the first
+//! `pty_list_shells`, and on Windows the first spawn, probe for shells.
+//! Sessions can be spawned before it finishes; they just pay for the
+//! detection themselves.
+
+use super::events::{self, EventSink, PtyEvent};
+use super::{get_default_shell, shells};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Instant;
+use tauri::AppHandle;
+
+static STARTED: AtomicBool = AtomicBool::new(false);
+static READY: OnceLock<PtySubsystemReady> = OnceLock::new();
+
+/// Payload of the `pty-subsystem-ready` event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PtySubsystemReady {
+    /// Shell `pty_spawn` starts when none is given
+    pub default_shell: String,
+    /// Shells found, as listed by `pty_list_shells`
+    pub shells: usize,
+}
+
+/// Start the one-time setup in the background. Only the first call does
+/// anything, so it is safe to call from several places.
+pub fn init(app: &AppHandle) {
+    init_with(events::sink(app));
+}
+
+fn init_with(sink: EventSink) {
+    if STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    tauri::async_runtime::spawn_blocking(move || {
+        let started = Instant::now();
+        let ready = PtySubsystemReady {
+            shells: shells::list_shells(false).len(),
+            default_shell: get_default_shell(None),
+        };
+        info!(
+            "Terminal subsystem ready after {}ms: {} shells, default {}",
+            started.elapsed().as_millis(),
+            ready.shells,
+            ready.default_shell
+        );
+        let _ = READY.set(ready.clone());
+        events::emit(sink.as_ref(), PtyEvent::SubsystemReady(ready));
+    });
+}
+
+/// The `pty-subsystem-ready` payload once setup has finished, for a frontend
+/// that starts listening after the event was sent
+#[tauri::command]
+pub fn pty_subsystem_ready() -> Option<PtySubsystemReady> {
+    READY.get().cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::events::CollectorSink;
+    use std::time::Duration;
+
+    #[test]
+    fn test_setup_runs_once() {
+        let collector = CollectorSink::new();
+        init_with(collector.clone());
+        init_with(collector.clone());
+        assert!(collector.wait_for(Duration::from_secs(30), |event| {
+            matches!(event, PtyEvent::SubsystemReady(_))
+        }));
+        // Time for a second run, had one started, to report too
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(collector.events().len(), 1);
+        assert_eq!(
+            pty_subsystem_ready().map(|ready| ready.default_shell),
+            Some(get_default_shell(None))
+        );
+    }
+}
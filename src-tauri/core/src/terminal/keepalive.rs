@@ -0,0 +1,70 @@
+//! Keepalive settings for idle sessions.
+//!
+//! Keepalive is only ever sent at the protocol level (e.g. SSH or telnet
+//! keepalive messages) of a remote backend. Nothing is written to a session's
+//! tty: whether a program tolerates stray input such as NUL bytes can't be
+//! known, so injecting it would trade one breakage for another. Sessions are
+//! local PTYs, which have no protocol underneath, so `Protocol` mode is
+//! recorded for them but sends nothing.
+
+use serde::{Deserialize, Serialize};
+
+/// Longest accepted keepalive interval
+const MAX_KEEPALIVE_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeepaliveMode {
+    #[default]
+    Off,
+    /// Protocol-level keepalive of a remote backend; a no-op for local sessions
+    Protocol,
+}
+
+/// Keepalive setting of a session, set with `pty_set_keepalive`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Keepalive {
+    pub mode: KeepaliveMode,
+    /// Interval between keepalives; 0 when off
+    pub interval_secs: u64,
+}
+
+impl Keepalive {
+    pub fn new(interval_secs: u64, mode: KeepaliveMode) -> Result<Self, String> {
+        match mode {
+            KeepaliveMode::Off => Ok(Self::default()),
+            KeepaliveMode::Protocol if interval_secs == 0 || interval_secs > MAX_KEEPALIVE_SECS => {
+                Err(format!(
+                    "Keepalive interval must be between 1 and {} seconds",
+                    MAX_KEEPALIVE_SECS
+                ))
+            }
+            KeepaliveMode::Protocol => Ok(Self {
+                mode,
+                interval_secs,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keepalive_validation() {
+        assert_eq!(
+            Keepalive::new(30, KeepaliveMode::Off).unwrap(),
+            Keepalive::default()
+        );
+        assert_eq!(
+            Keepalive::new(30, KeepaliveMode::Protocol).unwrap(),
+            Keepalive {
+                mode: KeepaliveMode::Protocol,
+                interval_secs: 30,
+            }
+        );
+        assert!(Keepalive::new(0, KeepaliveMode::Protocol).is_err());
+        assert!(Keepalive::new(MAX_KEEPALIVE_SECS + 1, KeepaliveMode::Protocol).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+//! Opt-in write latency tracking for diagnosing input lag.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Number of most recent writes the percentiles are computed over
+const LATENCY_WINDOW_SIZE: usize = 512;
+
+/// Rolling window of recent write latencies
+#[derive(Debug, Default)]
+pub struct LatencyWindow {
+    samples_us: VecDeque<u64>,
+    total: u64,
+}
+
+/// Latency window of a session, shared with its write queue so that queued
+/// writes count too. `None` while tracking is off.
+pub type SharedLatency = Arc<Mutex<Option<LatencyWindow>>>;
+
+/// Latency summary reported by `pty_get_info`, in microseconds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencyStats {
+    /// Writes recorded since tracking was enabled
+    pub total: u64,
+    /// Writes in the rolling window the percentiles cover
+    pub window: usize,
+    pub p50_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+impl LatencyWindow {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, elapsed: Duration) {
+        if self.samples_us.len() == LATENCY_WINDOW_SIZE {
+            self.samples_us.pop_front();
+        }
+        self.samples_us
+            .push_back(elapsed.as_micros().min(u64::MAX as u128) as u64);
+        self.total += 1;
+    }
+
+    /// Percentiles over the window, or `None` before the first write
+    pub fn stats(&self) -> Option<LatencyStats> {
+        if self.samples_us.is_empty() {
+            return None;
+        }
+
+        let mut sorted: Vec<u64> = self.samples_us.iter().copied().collect();
+        sorted.sort_unstable();
+        let percentile = |p: usize| sorted[(sorted.len() - 1) * p / 100];
+        Some(LatencyStats {
+            total: self.total,
+            window: sorted.len(),
+            p50_us: percentile(50),
+            p99_us: percentile(99),
+            max_us: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_percentiles() {
+        let mut window = LatencyWindow::new();
+        assert!(window.stats().is_none());
+
+        for us in 1..=100 {
+            window.record(Duration::from_micros(us));
+        }
+        let stats = window.stats().unwrap();
+        assert_eq!(stats.total, 100);
+        assert_eq!(stats.p50_us, 50);
+        assert_eq!(stats.p99_us, 99);
+        assert_eq!(stats.max_us, 100);
+    }
+
+    #[test]
+    fn test_latency_window_is_rolling() {
+        let mut window = LatencyWindow::new();
+        window.record(Duration::from_secs(1));
+        for _ in 0..LATENCY_WINDOW_SIZE {
+            window.record(Duration::from_micros(10));
+        }
+        let stats = window.stats().unwrap();
+        assert_eq!(stats.total, LATENCY_WINDOW_SIZE as u64 + 1);
+        assert_eq!(stats.window, LATENCY_WINDOW_SIZE);
+        assert_eq!(stats.max_us, 10);
+    }
+}
@@ -0,0 +1,148 @@
+//! Resource limits (rlimits) applied to a session's shell before it starts.
+//!
+//! Limited sessions are started through the pre-exec chain (see `pre_exec`),
+//! which calls `setrlimit` in the child. If any limit can't be applied the
+//! spawn fails; the shell never runs without the limits it was asked to have.
+
+use serde::{Deserialize, Serialize};
+
+/// Optional limits for the shell and everything it starts. Unix only.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResourceLimits {
+    /// CPU time in seconds (`RLIMIT_CPU`)
+    pub cpu_seconds: Option<u64>,
+    /// Address space in bytes (`RLIMIT_AS`). Not enforced by macOS.
+    pub memory_bytes: Option<u64>,
+    /// Open file descriptors (`RLIMIT_NOFILE`)
+    pub open_files: Option<u64>,
+    /// Processes of the user (`RLIMIT_NPROC`)
+    pub processes: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Check that the limits could be applied, without spawning anything
+    pub fn check(&self) -> Result<(), String> {
+        #[cfg(unix)]
+        {
+            unix::validate(&unix::requested(self))
+        }
+        #[cfg(not(unix))]
+        {
+            if self.is_empty() {
+                Ok(())
+            } else {
+                Err("Resource limits are only supported on Unix".to_string())
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::super::pre_exec::{self, LimitResource, PreExecAction, PreExecChain};
+    use super::ResourceLimits;
+    use log::info;
+    use portable_pty::{CommandBuilder, MasterPty};
+    use std::io;
+
+    /// (resource, requested value, name) for every limit that is set
+    pub(super) fn requested(limits: &ResourceLimits) -> Vec<(LimitResource, u64, &'static str)> {
+        [
+            (libc::RLIMIT_CPU, limits.cpu_seconds, "CPU seconds"),
+            (libc::RLIMIT_AS, limits.memory_bytes, "memory bytes"),
+            (libc::RLIMIT_NOFILE, limits.open_files, "open files"),
+            (libc::RLIMIT_NPROC, limits.processes, "processes"),
+        ]
+        .into_iter()
+        .filter_map(|(resource, value, name)| value.map(|value| (resource, value, name)))
+        .collect()
+    }
+
+    /// Check the limits against the current hard limits, which an unprivileged
+    /// process can't raise, so the error names the offending limit
+    pub(super) fn validate(limits: &[(LimitResource, u64, &'static str)]) -> Result<(), String> {
+        for &(resource, value, name) in limits {
+            let mut current = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            // SAFETY: `current` is a valid, writable rlimit
+            if unsafe { libc::getrlimit(resource, &mut current) } != 0 {
+                return Err(format!(
+                    "Failed to read current {} limit: {}",
+                    name,
+                    io::Error::last_os_error()
+                ));
+            }
+            let over_hard_limit = current.rlim_max != libc::RLIM_INFINITY
+                && (value as libc::rlim_t) > current.rlim_max;
+            if over_hard_limit {
+                return Err(format!(
+                    "Requested limit of {} {} exceeds the hard limit of {}",
+                    value, name, current.rlim_max
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Start `cmd` on the PTY's slave side with `limits` applied
+    pub fn spawn_limited(
+        master: &dyn MasterPty,
+        cmd: &CommandBuilder,
+        limits: &ResourceLimits,
+    ) -> Result<Box<dyn portable_pty::Child + Send + Sync>, String> {
+        let limits = requested(limits);
+        validate(&limits)?;
+
+        let mut chain = PreExecChain::new();
+        for (resource, value, _) in limits {
+            chain.push(PreExecAction::SetLimit { resource, value });
+        }
+        let child = pre_exec::spawn(master, cmd, chain)
+            .map_err(|e| format!("{} with resource limits", e))?;
+        info!("Spawned shell with resource limits");
+        Ok(child)
+    }
+}
+
+#[cfg(unix)]
+pub use unix::spawn_limited;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resource_limits_deserialize_partially() {
+        let limits: ResourceLimits = serde_json::from_str(r#"{"open_files":256}"#).unwrap();
+        assert_eq!(limits.open_files, Some(256));
+        assert_eq!(limits.cpu_seconds, None);
+        assert!(!limits.is_empty());
+        assert!(ResourceLimits::default().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_limit_above_hard_limit_is_rejected() {
+        let limits = ResourceLimits {
+            open_files: Some(u64::MAX - 1),
+            ..Default::default()
+        };
+        let result = super::unix::validate(&super::unix::requested(&limits));
+
+        // An unlimited hard limit (e.g. running as root) accepts anything
+        let mut current = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: `current` is a valid, writable rlimit
+        unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut current) };
+        assert_eq!(result.is_err(), current.rlim_max != libc::RLIM_INFINITY);
+    }
+}
@@ -0,0 +1,108 @@
+//! Streaming pattern matching over ANSI-stripped PTY output.
+
+use regex::Regex;
+
+/// Trailing text kept between chunks in regex mode. Regex matches that span
+/// more than this many bytes of output can be missed.
+const REGEX_WINDOW_BYTES: usize = 8 * 1024;
+
+enum MatchPattern {
+    Substring(String),
+    Regex(Regex),
+}
+
+/// Matches a pattern against output as it streams in, including matches
+/// that straddle a chunk boundary
+pub struct OutputMatcher {
+    pattern: MatchPattern,
+    window: String,
+}
+
+impl OutputMatcher {
+    pub fn new(pattern: &str, is_regex: bool) -> Result<Self, String> {
+        if pattern.is_empty() {
+            return Err("Pattern must not be empty".to_string());
+        }
+
+        let pattern = if is_regex {
+            let regex = Regex::new(pattern).map_err(|e| format!("Invalid regex pattern: {}", e))?;
+            MatchPattern::Regex(regex)
+        } else {
+            MatchPattern::Substring(pattern.to_string())
+        };
+
+        Ok(Self {
+            pattern,
+            window: String::new(),
+        })
+    }
+
+    /// Feed the next chunk of stripped output. Returns true once the pattern matched.
+    pub fn feed(&mut self, chunk: &str) -> bool {
+        self.window.push_str(chunk);
+
+        let matched = match &self.pattern {
+            MatchPattern::Substring(needle) => self.window.contains(needle.as_str()),
+            MatchPattern::Regex(regex) => regex.is_match(&self.window),
+        };
+
+        if !matched {
+            self.trim_window();
+        }
+        matched
+    }
+
+    /// Forget buffered text, e.g. after output was skipped
+    pub fn reset(&mut self) {
+        self.window.clear();
+    }
+
+    /// Keep only the tail that could still be part of a future match
+    fn trim_window(&mut self) {
+        let keep = match &self.pattern {
+            MatchPattern::Substring(needle) => needle.len().saturating_sub(1),
+            MatchPattern::Regex(_) => REGEX_WINDOW_BYTES,
+        };
+
+        if self.window.len() > keep {
+            let mut cut = self.window.len() - keep;
+            while !self.window.is_char_boundary(cut) {
+                cut += 1;
+            }
+            self.window.drain(..cut);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substring_match_across_chunks() {
+        let mut matcher = OutputMatcher::new("$ ready", false).unwrap();
+        assert!(!matcher.feed("booting...\nuser@host"));
+        assert!(!matcher.feed(" $ rea"));
+        assert!(matcher.feed("dy\n"));
+    }
+
+    #[test]
+    fn test_substring_window_is_bounded() {
+        let mut matcher = OutputMatcher::new("abc", false).unwrap();
+        assert!(!matcher.feed(&"x".repeat(10_000)));
+        assert!(matcher.window.len() <= 2);
+    }
+
+    #[test]
+    fn test_regex_match_across_chunks() {
+        let mut matcher = OutputMatcher::new(r"exit code: \d+", true).unwrap();
+        assert!(!matcher.feed("build finished, exit co"));
+        assert!(matcher.feed("de: 0\n"));
+    }
+
+    #[test]
+    fn test_invalid_patterns_are_rejected() {
+        assert!(OutputMatcher::new("", false).is_err());
+        assert!(OutputMatcher::new("(unclosed", true).is_err());
+    }
+}
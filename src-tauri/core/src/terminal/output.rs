@@ -0,0 +1,981 @@
+//! Per-session output processing shared between the read loop and commands.
+
+use super::ansi::{AnsiParser, AnsiStripper, Perform, Utf8Decoder};
+use super::bell::{BellLimiter, DEFAULT_BELL_WINDOW_MS};
+use super::caps::{
+    AltScreenCap, AnswerbackCap, BellCap, CommandMarksCap, ControlModeCap, CursorReportsCap,
+    CwdCap, HyperlinksCap, ParserCaps, SizeReportsCap, TitleCap,
+};
+use super::control_mode::{ControlModeChange, ControlProtocol};
+use super::cursor::CursorModel;
+use super::encoding::OutputDecoder;
+use super::hyperlink::{parse_osc8, Hyperlink, LinkStart, OpenLink};
+use super::scrollback::{Scrollback, ScrollbackLimit, DEFAULT_SCROLLBACK_BYTES};
+use super::shell_integration::{CommandCapture, CommandMark, CommandSpans};
+use super::size_report::WindowSize;
+use super::title::{TitleOp, TitleState};
+use super::PtySpawnOptions;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use encoding_rs::Encoding;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Longest unfinished escape sequence held back at a chunk boundary. Longer ones
+/// (e.g. a large OSC 52 payload) are passed on as they arrive.
+const MAX_HELD_SEQUENCE_BYTES: usize = 4096;
+
+/// How bytes held back for an incomplete UTF-8 character are passed on when
+/// the PTY closes and the character can no longer be completed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InvalidUtf8 {
+    /// Replace the incomplete character with U+FFFD
+    #[default]
+    Replace,
+    /// Pass the raw bytes on base64-encoded
+    Base64,
+}
+
+/// Output still held back when the PTY closes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Leftover {
+    Text(String),
+    /// Raw bytes that aren't valid UTF-8, base64-encoded
+    Base64(String),
+}
+
+/// Result of processing one chunk of output
+#[derive(Debug, Default)]
+pub struct ProcessedOutput {
+    /// Raw output to forward to the frontend, cut so that escape sequences and
+    /// UTF-8 characters are never split between two chunks
+    pub data: String,
+    /// ANSI-stripped text
+    pub text: String,
+    /// Set when a `pty-bell` should be emitted, to the number of bells
+    /// suppressed since the previous one
+    pub bell: Option<u32>,
+    /// Links (OSC 8) completed in this chunk, when hyperlink events are enabled
+    pub hyperlinks: Vec<Hyperlink>,
+    /// New window title, when it was set or restored in this chunk
+    pub title: Option<String>,
+    /// New working directory, when the shell reported a different one (OSC 7)
+    pub cwd: Option<String>,
+    /// Replies to cursor position and window size queries, to write back to the PTY
+    pub replies: String,
+    /// Control mode entered or left in this chunk, in order
+    pub control_mode: Vec<ControlModeChange>,
+    /// Encoding detection settled on with this chunk
+    pub encoding: Option<&'static Encoding>,
+}
+
+/// Output state of a session. The read loop feeds every chunk through
+/// [`OutputState::process`]; commands read the tracked state.
+#[derive(Debug)]
+pub struct OutputState {
+    parser: AnsiParser,
+    decoder: Utf8Decoder,
+    /// Decodes output read in a legacy encoding to UTF-8, see `detect_encoding`
+    pub encoding: OutputDecoder,
+    /// Whether a full-screen program has switched to the alternate screen
+    pub alt_screen: bool,
+    /// Keep appending to scrollback while the alternate screen is active
+    pub capture_alt_screen: bool,
+    pub scrollback: Scrollback,
+    /// Output cut off at the end of the last chunk: the start of an escape
+    /// sequence or of a UTF-8 character
+    held_bytes: Vec<u8>,
+    /// Whether the shell was started with our integration scripts
+    pub shell_integration: bool,
+    /// Whether the shell has reported command boundaries (OSC 133), either through
+    /// our integration or its own
+    pub command_marks_seen: bool,
+    /// Pending `pty_capture_next` call
+    pub capture: Option<CommandCapture>,
+    bell_limiter: BellLimiter,
+    /// Number of prompts the shell has reported (OSC 133;A)
+    pub prompts: u64,
+    /// Where the output of recent commands lies in the scrollback
+    pub commands: CommandSpans,
+    /// When output was last read
+    pub last_output: Option<Instant>,
+    /// Working directory last reported by the shell through OSC 7
+    pub cwd: Option<String>,
+    /// Window title and the title stack
+    pub title: TitleState,
+    /// Report OSC 8 hyperlinks in [`ProcessedOutput::hyperlinks`]
+    pub hyperlink_events: bool,
+    open_link: Option<OpenLink>,
+    /// Best-effort cursor position, see `pty_cursor_position`
+    pub cursor: CursorModel,
+    /// Answer cursor position requests from [`OutputState::cursor`]
+    pub answer_cursor_queries: bool,
+    /// Size used to answer window size queries, when `auto_respond_size` is set
+    pub size_reports: Option<WindowSize>,
+    /// Reply to ENQ, see `pty_set_answerback`
+    pub answerback: Option<String>,
+    pub invalid_utf8: InvalidUtf8,
+    /// Forward output in [`ProcessedOutput::data`]. While off, output is only
+    /// kept in scrollback.
+    pub emit_output: bool,
+    /// Control protocol a multiplexer is speaking instead of terminal output
+    pub control_mode: Option<ControlProtocol>,
+}
+
+/// Collects what the parser recognizes in a single chunk
+struct ChunkPerform<'a> {
+    stripped: &'a mut Vec<u8>,
+    /// Length of the held-back sequence bytes that precede the chunk
+    held_len: usize,
+    alt_screen: bool,
+    pending_alt_screen: Option<bool>,
+    /// (offset, alt_screen) pairs marking where the alternate screen was toggled,
+    /// relative to the held-back bytes followed by the chunk
+    alt_screen_toggles: &'a mut Vec<(usize, bool)>,
+    /// Command boundaries with their offset into the stripped output
+    command_marks: &'a mut Vec<(usize, CommandMark)>,
+    /// Command mark dispatched, waiting for the span of its sequence
+    pending_command_mark: Option<CommandMark>,
+    /// Command boundaries with the offset just past their sequence, relative to
+    /// the held-back bytes followed by the chunk
+    raw_command_marks: &'a mut Vec<(usize, CommandMark)>,
+    bells: u32,
+    reported_cwd: Option<String>,
+    /// Links opened (`Some`) and closed (`None`) with their offset into the
+    /// stripped output
+    link_marks: &'a mut Vec<(usize, Option<LinkStart>)>,
+    title_ops: &'a mut Vec<TitleOp>,
+    cursor: &'a mut CursorModel,
+    answer_cursor_queries: bool,
+    size_reports: Option<WindowSize>,
+    answerback: Option<&'a str>,
+    replies: &'a mut String,
+    control_mode: Option<ControlProtocol>,
+    control_mode_changes: &'a mut Vec<ControlModeChange>,
+}
+
+impl Perform for ChunkPerform<'_> {
+    fn print(&mut self, bytes: &[u8]) {
+        self.stripped.extend_from_slice(bytes);
+        self.cursor.print(bytes);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        self.cursor.execute(byte);
+        match byte {
+            b'\n' | b'\t' => self.stripped.push(byte),
+            // BEL terminating an OSC is consumed by the parser and never gets here
+            0x07 => self.bells += 1,
+            0x05 => {
+                if let Some(answerback) = self.answerback {
+                    self.replies.push_str(answerback);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(
+        &mut self,
+        prefix: Option<u8>,
+        params: &[u16],
+        intermediates: &[u8],
+        action: u8,
+    ) {
+        if prefix.is_none() && intermediates.is_empty() {
+            if action == b'n' && params.first() == Some(&6) {
+                if self.answer_cursor_queries {
+                    self.replies.push_str(&self.cursor.report());
+                }
+            } else {
+                self.cursor.csi(params, action);
+            }
+        }
+        if prefix.is_none() && action == b't' {
+            if let Some(reply) = self
+                .size_reports
+                .filter(|_| intermediates.is_empty())
+                .and_then(|size| size.reply(params))
+            {
+                self.replies.push_str(&reply);
+            } else if let Some(op) = TitleOp::from_csi(params) {
+                self.title_ops.push(op);
+            }
+            return;
+        }
+        if prefix != Some(b'?') || !matches!(action, b'h' | b'l') {
+            return;
+        }
+        // 1049 is what modern programs use; 47 and 1047 are the older variants
+        if params.iter().any(|p| matches!(p, 47 | 1047 | 1049)) {
+            self.pending_alt_screen = Some(action == b'h');
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], byte: u8) {
+        if !intermediates.is_empty() {
+            return;
+        }
+        self.cursor.esc(byte);
+        // RIS (full reset) always returns to the normal screen
+        if byte == b'c' {
+            self.pending_alt_screen = Some(false);
+        }
+    }
+
+    fn osc_dispatch(&mut self, data: &[u8]) {
+        if let Some(mark) = CommandMark::parse(data) {
+            self.command_marks.push((self.stripped.len(), mark));
+            self.pending_command_mark = Some(mark);
+        } else if let Some(op) = TitleOp::from_osc(data) {
+            self.title_ops.push(op);
+        } else if let Some(link) = parse_osc8(data) {
+            self.link_marks.push((self.stripped.len(), link));
+        } else if let Some(url) = data.strip_prefix(b"7;") {
+            if let Some(cwd) = parse_osc7_cwd(url) {
+                self.reported_cwd = Some(cwd);
+            }
+        }
+    }
+
+    fn dcs_hook(&mut self, params: &[u16], intermediates: &[u8], action: u8) {
+        if let Some(protocol) = ControlProtocol::from_dcs(params, intermediates, action) {
+            self.control_mode = Some(protocol);
+            self.control_mode_changes.push(ControlModeChange {
+                protocol,
+                active: true,
+            });
+        }
+    }
+
+    fn dcs_unhook(&mut self) {
+        if let Some(protocol) = self.control_mode.take() {
+            self.control_mode_changes.push(ControlModeChange {
+                protocol,
+                active: false,
+            });
+        }
+    }
+
+    fn sequence_span(&mut self, start: Option<usize>, end: usize) {
+        if let Some(mark) = self.pending_command_mark.take() {
+            self.raw_command_marks.push((self.held_len + end, mark));
+        }
+        if let Some(alt_screen) = self.pending_alt_screen.take() {
+            if alt_screen != self.alt_screen {
+                // Both switch sequences belong to the normal screen's history, so
+                // entering splits after the sequence and leaving splits before it
+                let offset = if alt_screen {
+                    self.held_len + end
+                } else {
+                    start.map_or(0, |start| self.held_len + start)
+                };
+                self.alt_screen = alt_screen;
+                self.alt_screen_toggles.push((offset, alt_screen));
+            }
+        }
+    }
+}
+
+/// Path of an OSC 7 `file://host/path` URL, percent-decoded
+fn parse_osc7_cwd(url: &[u8]) -> Option<String> {
+    let rest = url.strip_prefix(b"file://")?;
+    // Skip the host name; the path starts at the next slash
+    let path = &rest[rest.iter().position(|&b| b == b'/')?..];
+
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < path.len() {
+        let hex = path
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (path[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    let mut cwd = String::from_utf8(decoded).ok()?;
+    // file://host/C:/Users -> C:/Users
+    if cwd.len() >= 3 && cwd.as_bytes()[2] == b':' && cwd.as_bytes()[1].is_ascii_alphabetic() {
+        cwd.remove(0);
+    }
+    Some(cwd)
+}
+
+/// Length of an incomplete UTF-8 character at the end of `bytes`
+fn incomplete_utf8_len(bytes: &[u8]) -> usize {
+    for len in 1..=bytes.len().min(3) {
+        let byte = bytes[bytes.len() - len];
+        if byte & 0xc0 == 0x80 {
+            // Continuation byte; keep looking for the lead byte
+            continue;
+        }
+        let needed = match byte {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        };
+        return if needed > len { len } else { 0 };
+    }
+    0
+}
+
+impl OutputState {
+    pub fn new(options: &PtySpawnOptions) -> Self {
+        let mut scrollback =
+            Scrollback::new(options.scrollback_bytes.unwrap_or(DEFAULT_SCROLLBACK_BYTES));
+        if options.compress_scrollback {
+            scrollback = scrollback.with_compression();
+        }
+        if let Some(max_lines) = options.max_scrollback_lines {
+            let limit = options.scrollback_limit.unwrap_or(ScrollbackLimit::Both);
+            scrollback = scrollback.with_max_lines(max_lines, limit);
+        }
+        Self {
+            parser: AnsiParser::new(),
+            decoder: Utf8Decoder::new(),
+            encoding: OutputDecoder::new(options.detect_encoding),
+            alt_screen: false,
+            capture_alt_screen: options.capture_alt_screen,
+            scrollback,
+            held_bytes: Vec::new(),
+            shell_integration: options.shell_integration,
+            command_marks_seen: false,
+            capture: None,
+            bell_limiter: BellLimiter::new(Duration::from_millis(
+                options.bell_window_ms.unwrap_or(DEFAULT_BELL_WINDOW_MS),
+            )),
+            prompts: 0,
+            commands: CommandSpans::default(),
+            last_output: None,
+            cwd: None,
+            title: TitleState::default(),
+            hyperlink_events: options.hyperlink_events,
+            open_link: None,
+            cursor: CursorModel::new(80, 24),
+            answer_cursor_queries: false,
+            size_reports: None,
+            answerback: None,
+            invalid_utf8: options.invalid_utf8,
+            emit_output: true,
+            control_mode: None,
+        }
+    }
+
+    /// Put content in the scrollback that wasn't read from the PTY, ahead of
+    /// the shell's output. Only the tail that fits the cap is kept, and it is
+    /// ended with a line break and attribute reset so that it can't restyle
+    /// what follows. Returns what was appended.
+    pub fn seed(&mut self, content: &[u8]) -> Vec<u8> {
+        let mut seeded = content[content.len().saturating_sub(self.scrollback.cap())..].to_vec();
+        if seeded.is_empty() {
+            return seeded;
+        }
+        if !seeded.ends_with(b"\n") {
+            seeded.extend_from_slice(b"\r\n");
+        }
+        seeded.extend_from_slice(b"\x1b[0m");
+        self.scrollback.append(&seeded);
+        seeded
+    }
+
+    /// Process a chunk of raw output: track terminal modes, append to
+    /// scrollback and return what to forward to the frontend.
+    pub fn process(&mut self, bytes: &[u8]) -> ProcessedOutput {
+        self.last_output = Some(Instant::now());
+        let decoded = self.encoding.decode(bytes);
+        let bytes: &[u8] = &decoded.bytes;
+        let mut stripped = Vec::with_capacity(bytes.len());
+        let mut toggles = Vec::new();
+        let mut command_marks = Vec::new();
+        let mut raw_command_marks = Vec::new();
+        let mut link_marks = Vec::new();
+        let mut title_ops = Vec::new();
+        let mut replies = String::new();
+        let mut control_mode_changes = Vec::new();
+        let mut perform = ChunkPerform {
+            stripped: &mut stripped,
+            held_len: self.held_bytes.len(),
+            alt_screen: self.alt_screen,
+            pending_alt_screen: None,
+            alt_screen_toggles: &mut toggles,
+            command_marks: &mut command_marks,
+            pending_command_mark: None,
+            raw_command_marks: &mut raw_command_marks,
+            bells: 0,
+            reported_cwd: None,
+            link_marks: &mut link_marks,
+            title_ops: &mut title_ops,
+            cursor: &mut self.cursor,
+            answer_cursor_queries: self.answer_cursor_queries,
+            size_reports: self.size_reports,
+            answerback: self.answerback.as_deref(),
+            replies: &mut replies,
+            control_mode: self.control_mode,
+            control_mode_changes: &mut control_mode_changes,
+        };
+        self.parser.advance(bytes, &mut perform);
+        let bells = perform.bells;
+        let reported_cwd = perform.reported_cwd.take();
+        self.control_mode = perform.control_mode;
+
+        let mut processed = ProcessedOutput {
+            bell: self.bell_limiter.ring(Instant::now(), bells),
+            replies,
+            control_mode: control_mode_changes,
+            encoding: decoded.detected,
+            ..Default::default()
+        };
+        if let Some(cwd) = reported_cwd {
+            if self.cwd.as_ref() != Some(&cwd) {
+                processed.cwd = Some(cwd.clone());
+            }
+            self.cwd = Some(cwd);
+        }
+        for op in title_ops {
+            if let Some(title) = self.title.apply(op) {
+                processed.title = Some(title);
+            }
+        }
+        self.track_commands(&stripped, command_marks);
+        if self.hyperlink_events {
+            processed.hyperlinks = self.track_links(&stripped, link_marks);
+        }
+
+        let mut data = std::mem::take(&mut self.held_bytes);
+        let held_len = data.len();
+        data.extend_from_slice(bytes);
+
+        // Hold back a sequence or character cut off at the end so that output
+        // is only ever split between complete sequences. In control mode the
+        // whole stream is one sequence, so it's passed on as it arrives.
+        let mut end = data.len() - incomplete_utf8_len(&data);
+        if let Some(start) = self
+            .parser
+            .unfinished_sequence_start()
+            .filter(|_| self.control_mode.is_none())
+        {
+            let start = start.map_or(0, |start| held_len + start);
+            if data.len() - start <= MAX_HELD_SEQUENCE_BYTES {
+                end = start;
+            }
+        }
+
+        // Split where the alternate screen was entered or left so that
+        // full-screen redraws stay out of the normal-screen history, and where
+        // commands start and end to find their output in it
+        let mut start = 0;
+        let mut raw_command_marks = raw_command_marks.into_iter().peekable();
+        for (offset, alt_screen) in toggles {
+            while let Some((mark_offset, mark)) =
+                raw_command_marks.next_if(|(mark_offset, _)| *mark_offset <= offset)
+            {
+                self.append_scrollback(&data[start..mark_offset]);
+                start = mark_offset;
+                self.commands.mark(mark, self.scrollback.total());
+            }
+            self.append_scrollback(&data[start..offset]);
+            start = offset;
+            self.alt_screen = alt_screen;
+        }
+        let end = end.max(start);
+        for (mark_offset, mark) in raw_command_marks {
+            let mark_offset = mark_offset.clamp(start, end);
+            self.append_scrollback(&data[start..mark_offset]);
+            start = mark_offset;
+            self.commands.mark(mark, self.scrollback.total());
+        }
+        self.append_scrollback(&data[start..end]);
+        self.held_bytes = data[end..].to_vec();
+        data.truncate(end);
+
+        if self.emit_output {
+            processed.data = String::from_utf8_lossy(&data).to_string();
+        }
+        self.decoder.decode(&stripped, &mut processed.text);
+        processed
+    }
+
+    /// ANSI-stripped output of a command by its number in the session, empty
+    /// once trimmed from the scrollback. `None` if no such command has run.
+    pub fn command_output(&self, index: u64) -> Option<String> {
+        if index >= self.commands.count() {
+            return None;
+        }
+        let Some(span) = self.commands.get(index) else {
+            return Some(String::new());
+        };
+        let end = span.end.unwrap_or_else(|| self.scrollback.total());
+        let data = self.scrollback.range(span.start, end).unwrap_or_default();
+        Some(AnsiStripper::new().strip(&data))
+    }
+
+    /// Pass on whatever is still held back, e.g. when the PTY closes. An
+    /// incomplete UTF-8 character is passed on as set by `invalid_utf8`.
+    pub fn flush(&mut self) -> Option<Leftover> {
+        let mut held = std::mem::take(&mut self.held_bytes);
+        held.extend(self.encoding.finish());
+        self.append_scrollback(&held);
+        if held.is_empty() || !self.emit_output {
+            return None;
+        }
+        let leftover = match std::str::from_utf8(&held) {
+            Ok(text) => Leftover::Text(text.to_string()),
+            Err(_) if self.invalid_utf8 == InvalidUtf8::Base64 => {
+                Leftover::Base64(STANDARD.encode(&held))
+            }
+            Err(_) => Leftover::Text(String::from_utf8_lossy(&held).to_string()),
+        };
+        Some(leftover)
+    }
+
+    /// The parsers active for this session and what they have tracked
+    pub fn capabilities(&self) -> ParserCaps {
+        ParserCaps {
+            alt_screen: AltScreenCap {
+                enabled: true,
+                active: self.alt_screen,
+                captured: self.capture_alt_screen,
+            },
+            cwd: CwdCap {
+                enabled: true,
+                cwd: self.cwd.clone(),
+            },
+            command_marks: CommandMarksCap {
+                enabled: true,
+                shell_integration: self.shell_integration,
+                seen: self.command_marks_seen,
+                capture_pending: self.capture.is_some(),
+            },
+            hyperlinks: HyperlinksCap {
+                enabled: self.hyperlink_events,
+                link_open: self.open_link.is_some(),
+            },
+            bell: BellCap {
+                enabled: true,
+                window_ms: self.bell_window().as_millis() as u64,
+            },
+            title: TitleCap {
+                enabled: true,
+                title: self.title.title.clone(),
+                stack_depth: self.title.stack_depth(),
+            },
+            cursor_reports: CursorReportsCap {
+                enabled: self.answer_cursor_queries,
+                position: Some(self.cursor.position()),
+            },
+            size_reports: SizeReportsCap {
+                enabled: self.size_reports.is_some(),
+            },
+            answerback: AnswerbackCap {
+                enabled: self.answerback.is_some(),
+            },
+            control_mode: ControlModeCap {
+                enabled: true,
+                protocol: self.control_mode,
+            },
+        }
+    }
+
+    /// Minimum interval between emitted bells
+    pub fn bell_window(&self) -> Duration {
+        self.bell_limiter.window()
+    }
+
+    /// Feed the pending capture the stripped output between command marks
+    fn track_commands(&mut self, stripped: &[u8], marks: Vec<(usize, CommandMark)>) {
+        if !marks.is_empty() {
+            self.command_marks_seen = true;
+        }
+        self.prompts += marks
+            .iter()
+            .filter(|(_, mark)| *mark == CommandMark::PromptStart)
+            .count() as u64;
+        let Some(mut capture) = self.capture.take() else {
+            return;
+        };
+
+        let mut start = 0;
+        for (offset, mark) in marks {
+            capture.push(&stripped[start..offset]);
+            start = offset;
+            match capture.mark(mark) {
+                Some(pending) => capture = pending,
+                None => return,
+            }
+        }
+        capture.push(&stripped[start..]);
+        self.capture = Some(capture);
+    }
+
+    /// Collect link text between OSC 8 marks and return the links that closed
+    fn track_links(
+        &mut self,
+        stripped: &[u8],
+        marks: Vec<(usize, Option<LinkStart>)>,
+    ) -> Vec<Hyperlink> {
+        let mut links = Vec::new();
+        let mut start = 0;
+        for (offset, mark) in marks {
+            if let Some(link) = self.open_link.as_mut() {
+                link.push(&stripped[start..offset]);
+            }
+            start = offset;
+            // Opening a link while one is open implicitly closes the first
+            if let Some(link) = self.open_link.take() {
+                links.push(link.finish());
+            }
+            self.open_link = mark.map(OpenLink::new);
+        }
+        if let Some(link) = self.open_link.as_mut() {
+            link.push(&stripped[start..]);
+        }
+        links
+    }
+
+    fn append_scrollback(&mut self, bytes: &[u8]) {
+        if !self.alt_screen || self.capture_alt_screen {
+            self.scrollback.append(bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scrollback_text(state: &OutputState) -> String {
+        String::from_utf8_lossy(&state.scrollback.contents()).to_string()
+    }
+
+    #[test]
+    fn test_seeded_scrollback_precedes_output() {
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        assert_eq!(state.seed(b"\x1b[31m$ make"), b"\x1b[31m$ make\r\n\x1b[0m");
+        // Seeding isn't output from the shell
+        assert!(state.last_output.is_none());
+        state.process(b"$ ");
+        assert_eq!(scrollback_text(&state), "\x1b[31m$ make\r\n\x1b[0m$ ");
+
+        let options = PtySpawnOptions {
+            scrollback_bytes: Some(64),
+            ..Default::default()
+        };
+        let mut state = OutputState::new(&options);
+        let mut history = b"first line\n".to_vec();
+        history.extend(std::iter::repeat_n(b'x', 100));
+        let seeded = state.seed(&history);
+        assert!(!seeded.starts_with(b"first"));
+        assert!(state.scrollback.len() <= 64);
+    }
+
+    #[test]
+    fn test_alt_screen_content_not_in_scrollback_by_default() {
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        state.process(b"$ vim notes.txt\r\n\x1b[?1049h\x1b[Heditor redraw");
+        assert!(state.alt_screen);
+        state.process(b" more redraw\x1b[?1049l$ ");
+        assert!(!state.alt_screen);
+
+        let text = scrollback_text(&state);
+        assert!(text.starts_with("$ vim notes.txt\r\n\x1b[?1049h"));
+        assert!(text.ends_with("\x1b[?1049l$ "));
+        assert!(!text.contains("redraw"));
+    }
+
+    #[test]
+    fn test_alt_screen_captured_when_enabled() {
+        let options = PtySpawnOptions {
+            capture_alt_screen: true,
+            ..Default::default()
+        };
+        let mut state = OutputState::new(&options);
+        state.process(b"\x1b[?1049hredraw\x1b[?1049l");
+        assert!(scrollback_text(&state).contains("redraw"));
+    }
+
+    #[test]
+    fn test_alt_screen_toggle_split_across_chunks() {
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        state.process(b"before\x1b[?10");
+        state.process(b"49hhidden\x1b[?1049");
+        state.process(b"lafter");
+        assert_eq!(scrollback_text(&state), "before\x1b[?1049h\x1b[?1049lafter");
+    }
+
+    #[test]
+    fn test_capture_collects_output_between_command_marks() {
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        let (tx, mut rx) = tokio::sync::oneshot::channel();
+        state.capture = Some(CommandCapture::new(tx));
+
+        state.process(b"$ ls\r\n\x1b]133;C\x07a.txt\r\n");
+        assert!(state.command_marks_seen);
+        state.process(b"b.txt\r\n\x1b]133;D;0\x07\x1b]133;A\x07$ ");
+
+        assert!(state.capture.is_none());
+        assert_eq!(state.prompts, 1);
+        let result = rx.try_recv().unwrap();
+        assert_eq!(result.output, "a.txt\nb.txt\n");
+        assert_eq!(result.exit_code, Some(0));
+    }
+
+    #[test]
+    fn test_command_output_from_scrollback() {
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        state.process(b"\x1b]133;A\x07$ ls\n\x1b]133;C\x07a.txt\n");
+        assert_eq!(state.command_output(0).as_deref(), Some("a.txt\n"));
+        // Marks split across chunks and a full-screen program in between
+        state.process(b"b.txt\n\x1b]133;D;0\x07\x1b]133;A\x07$ vim\n\x1b]13");
+        state.process(b"3;C\x07\x1b[?1049hediting\x1b[?1049lsaved\n\x1b]133;D;0\x07");
+        state.process(b"\x1b]133;A\x07$ ");
+
+        assert_eq!(state.command_output(0).as_deref(), Some("a.txt\nb.txt\n"));
+        assert_eq!(state.command_output(1).as_deref(), Some("saved\n"));
+        assert_eq!(state.command_output(2), None);
+
+        state.scrollback.set_cap(4);
+        assert_eq!(state.command_output(0).as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_character_cut_off_at_close_is_flushed() {
+        // The last read ends in the middle of a four-byte emoji
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        assert_eq!(state.process(b"done \xf0\x9f\x98").data, "done ");
+        assert_eq!(
+            state.flush(),
+            Some(Leftover::Text(char::REPLACEMENT_CHARACTER.to_string()))
+        );
+        assert_eq!(state.flush(), None);
+
+        let options = PtySpawnOptions {
+            invalid_utf8: InvalidUtf8::Base64,
+            ..Default::default()
+        };
+        let mut state = OutputState::new(&options);
+        state.process(b"done \xf0\x9f\x98");
+        assert_eq!(state.flush(), Some(Leftover::Base64("8J+Y".to_string())));
+        assert_eq!(state.scrollback.contents(), b"done \xf0\x9f\x98");
+
+        // Complete output held back is passed on as text either way
+        let mut state = OutputState::new(&options);
+        state.process(b"\x1b]0;tit");
+        assert_eq!(
+            state.flush(),
+            Some(Leftover::Text("\x1b]0;tit".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_bell_storm_emits_once_per_window() {
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        let mut emitted = 0;
+        for _ in 0..100 {
+            if state.process(b"\x07").bell.is_some() {
+                emitted += 1;
+            }
+        }
+        assert_eq!(emitted, 1);
+
+        // A BEL ending an OSC sequence is not a bell
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        assert!(state.process(b"\x1b]0;title\x07").bell.is_none());
+    }
+
+    #[test]
+    fn test_tmux_control_mode_detected() {
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        let processed = state.process(b"$ tmux -CC\r\n\x1bP1000p%begin 1 2 0\r\n");
+        assert_eq!(
+            processed.control_mode,
+            vec![ControlModeChange {
+                protocol: ControlProtocol::Tmux,
+                active: true,
+            }]
+        );
+        assert_eq!(state.control_mode, Some(ControlProtocol::Tmux));
+        // Not held back waiting for the end of the sequence
+        assert!(processed.data.ends_with("%begin 1 2 0\r\n"));
+
+        let processed = state.process(b"%output %1 hi\r\n");
+        assert!(processed.control_mode.is_empty());
+        assert_eq!(processed.data, "%output %1 hi\r\n");
+
+        let processed = state.process(b"%exit\r\n\x1b\\$ ");
+        assert_eq!(
+            processed.control_mode,
+            vec![ControlModeChange {
+                protocol: ControlProtocol::Tmux,
+                active: false,
+            }]
+        );
+        assert_eq!(state.control_mode, None);
+    }
+
+    #[test]
+    fn test_osc7_updates_cwd() {
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        let processed = state.process(b"\x1b]7;file://host/home/me/my%20project\x07$ ");
+        assert_eq!(processed.cwd.as_deref(), Some("/home/me/my project"));
+        assert_eq!(state.cwd.as_deref(), Some("/home/me/my project"));
+        // Reporting the same directory again is not a change
+        let processed = state.process(b"\x1b]7;file://host/home/me/my%20project\x07$ ");
+        assert_eq!(processed.cwd, None);
+
+        assert_eq!(
+            parse_osc7_cwd(b"file://desktop/C:/Users/me").as_deref(),
+            Some("C:/Users/me")
+        );
+        assert_eq!(parse_osc7_cwd(b"https://example.com/"), None);
+    }
+
+    #[test]
+    fn test_forwarded_data_does_not_split_sequences() {
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        let first = state.process(b"ls\x1b]8;;file:///tmp/a\x1b");
+        assert_eq!(first.data, "ls");
+        let second = state.process(b"\\a\x1b]8;;\x1b\\ \xe2\x82");
+        assert_eq!(second.data, "\x1b]8;;file:///tmp/a\x1b\\a\x1b]8;;\x1b\\ ");
+        assert_eq!(state.process(b"\xac").data, "€");
+    }
+
+    #[test]
+    fn test_hyperlink_events_when_enabled() {
+        let options = PtySpawnOptions {
+            hyperlink_events: true,
+            ..Default::default()
+        };
+        let mut state = OutputState::new(&options);
+        let first = state.process(b"\x1b]8;id=1;https://example.com\x1b\\exam");
+        assert!(first.hyperlinks.is_empty());
+        let second = state.process(b"ple\x1b]8;;\x1b\\ done");
+        assert_eq!(
+            second.hyperlinks,
+            vec![Hyperlink {
+                url: "https://example.com".to_string(),
+                id: Some("1".to_string()),
+                text: "example".to_string(),
+            }]
+        );
+
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        let output = state.process(b"\x1b]8;;https://example.com\x07x\x1b]8;;\x07");
+        assert!(output.hyperlinks.is_empty());
+    }
+
+    #[test]
+    fn test_capabilities_reflect_tracked_state() {
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        state.process(b"\x1b]7;file:///tmp\x07\x1b[?1049h");
+        let caps = state.capabilities();
+        assert!(caps.alt_screen.active);
+        assert_eq!(caps.cwd.cwd.as_deref(), Some("/tmp"));
+        assert!(!caps.hyperlinks.enabled);
+        assert!(!caps.command_marks.seen);
+    }
+
+    #[test]
+    fn test_title_restored_when_program_pops_it() {
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        assert_eq!(
+            state.process(b"\x1b]0;~/project\x07").title.as_deref(),
+            Some("~/project")
+        );
+        state.process(b"\x1b[22;0t\x1b]2;vim notes.txt\x07");
+        assert_eq!(
+            state.process(b"\x1b[23;0t$ ").title.as_deref(),
+            Some("~/project")
+        );
+    }
+
+    #[test]
+    fn test_process_returns_stripped_text() {
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        assert_eq!(state.process(b"\x1b[32mok\x1b[0m\r\n").text, "ok\n");
+    }
+
+    #[test]
+    fn test_cursor_queries_answered_only_when_enabled() {
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        assert!(state.process(b"abc\x1b[6n").replies.is_empty());
+        // Tracked regardless
+        assert_eq!(state.cursor.position(), (0, 3));
+
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        state.answer_cursor_queries = true;
+        let processed = state.process(b"\x1b[31mabc\x1b[0m\x1b[6n\r\n\x1b[6n");
+        assert_eq!(processed.replies, "\x1b[1;4R\x1b[2;1R");
+        // The request itself is still forwarded
+        assert!(processed.data.ends_with("\x1b[6n"));
+    }
+
+    #[test]
+    fn test_enq_answered_only_with_answerback() {
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        let processed = state.process(b"login\x05");
+        assert!(processed.replies.is_empty());
+        assert_eq!(processed.data, "login\x05");
+
+        state.answerback = Some("VT100".to_string());
+        let processed = state.process(b"\x05ok\x05");
+        assert_eq!(processed.replies, "VT100VT100");
+        assert_eq!(processed.data, "\x05ok\x05");
+    }
+
+    #[test]
+    fn test_size_queries_answered_only_when_enabled() {
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        assert!(state.process(b"\x1b[18t").replies.is_empty());
+
+        state.size_reports = Some(WindowSize {
+            cols: 80,
+            rows: 24,
+            pixel_width: 0,
+            pixel_height: 0,
+        });
+        let processed = state.process(b"\x1b[18t\x1b[14t\x1b[22;0t");
+        assert_eq!(processed.replies, "\x1b[8;24;80t");
+        assert_eq!(state.title.stack_depth(), 1);
+    }
+
+    #[test]
+    fn test_disabled_emission_keeps_scrollback() {
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        state.emit_output = false;
+        let processed = state.process(b"hidden\r\n\x1b]0;tab\x07");
+        assert!(processed.data.is_empty());
+        // Everything else is still tracked
+        assert_eq!(processed.title.as_deref(), Some("tab"));
+
+        state.emit_output = true;
+        assert_eq!(state.process(b"shown").data, "shown");
+        assert_eq!(
+            state.scrollback.contents(),
+            b"hidden\r\n\x1b]0;tab\x07shown"
+        );
+    }
+
+    #[test]
+    fn test_legacy_output_is_decoded_before_processing() {
+        let mut state = OutputState::new(&PtySpawnOptions::default());
+        state.encoding.set_encoding(encoding_rs::GBK);
+        let (bytes, _, _) = encoding_rs::GBK.encode("\x1b]0;日志\x07中文\r\n");
+        let processed = state.process(&bytes);
+        assert_eq!(processed.title.as_deref(), Some("日志"));
+        assert_eq!(processed.text, "中文\n");
+        assert_eq!(
+            state.scrollback.contents(),
+            "\x1b]0;日志\x07中文\r\n".as_bytes()
+        );
+    }
+}
@@ -0,0 +1,256 @@
+//! Pasting text, optionally checked for content that could act on its own.
+//!
+//! With `safe_paste`, a paste containing any of the following is held back and
+//! a `pty-paste-warning` asks the user to confirm it first:
+//! - `EscapeSequence`: an ESC byte (0x1b), which can start an escape sequence,
+//!   including one that ends bracketed paste early
+//! - `ControlCharacter`: a C0 control other than TAB, LF and CR (0x00-0x08,
+//!   0x0b, 0x0c, 0x0e-0x1a, 0x1c-0x1f), DEL (0x7f) or a C1 control
+//!   (U+0080-U+009F), which can e.g. interrupt, suspend or edit the command line
+//! - `LineBreak`: an LF or CR outside bracketed paste, which runs the text
+//!   before it as a command
+
+use super::ansi::strip_ansi;
+use super::events::{self, PtyEvent};
+use super::{write_session, PTY_SESSIONS};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tauri::AppHandle;
+
+const ESC: char = '\x1b';
+const BRACKETED_PASTE_START: &str = "\x1b[200~";
+const BRACKETED_PASTE_END: &str = "\x1b[201~";
+
+/// Content that makes a paste need confirmation with `safe_paste`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PasteHazard {
+    EscapeSequence,
+    ControlCharacter,
+    LineBreak,
+}
+
+/// Payload of the `pty-paste-warning` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PtyPasteWarning {
+    pub pty_id: String,
+    /// Pass to `pty_paste_confirm`
+    pub paste_id: String,
+    pub hazards: Vec<PasteHazard>,
+}
+
+/// Answer to a `pty-paste-warning`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PasteDecision {
+    /// Paste the text as it is
+    Paste,
+    /// Paste it without escape sequences and control characters; outside
+    /// bracketed paste, line breaks become spaces so nothing runs on its own
+    Strip,
+    Cancel,
+}
+
+/// A paste held back until the user confirms it
+#[derive(Debug)]
+pub struct PendingPaste {
+    paste_id: String,
+    data: String,
+    bracketed: bool,
+}
+
+fn is_control(c: char) -> bool {
+    matches!(c, '\0'..='\x08' | '\x0b' | '\x0c' | '\x0e'..='\x1a' | '\x1c'..='\x1f' | '\x7f')
+        || ('\u{80}'..='\u{9f}').contains(&c)
+}
+
+/// What in `data` needs confirmation before pasting it
+pub fn scan(data: &str, bracketed: bool) -> Vec<PasteHazard> {
+    let mut hazards = Vec::new();
+    if data.contains(ESC) {
+        hazards.push(PasteHazard::EscapeSequence);
+    }
+    if data.chars().any(is_control) {
+        hazards.push(PasteHazard::ControlCharacter);
+    }
+    if !bracketed && data.contains(['\n', '\r']) {
+        hazards.push(PasteHazard::LineBreak);
+    }
+    hazards
+}
+
+/// `data` without escape sequences and control characters, and outside
+/// bracketed paste without line breaks
+pub fn strip(data: &str, bracketed: bool) -> String {
+    let text: String = strip_ansi(&data.replace("\r\n", "\n").replace('\r', "\n"))
+        .chars()
+        .filter(|&c| !is_control(c))
+        .collect();
+    if bracketed {
+        text
+    } else {
+        text.replace('\n', " ")
+    }
+}
+
+/// Text to write for a paste: line breaks as typed Enter keys, and inside a
+/// bracketed paste the paste end marker removed until none is left, since
+/// removing one can join the text around it into another
+pub fn paste_data(data: &str, bracketed: bool) -> String {
+    let mut text = data.replace("\r\n", "\r").replace('\n', "\r");
+    if bracketed {
+        while text.contains(BRACKETED_PASTE_END) {
+            text = text.replace(BRACKETED_PASTE_END, "");
+        }
+        format!("{}{}{}", BRACKETED_PASTE_START, text, BRACKETED_PASTE_END)
+    } else {
+        text
+    }
+}
+
+/// Paste text into a session, wrapped in bracketed paste markers with
+/// `bracketed` (when the program enabled bracketed paste). With `safe_paste`,
+/// text containing escape sequences, control characters or, outside bracketed
+/// paste, line breaks is not written: a `pty-paste-warning` is emitted and the
+/// returned paste id must be confirmed with `pty_paste_confirm`. Returns `None`
+/// when the text was written. A new held paste replaces an unconfirmed one.
+#[tauri::command]
+pub fn pty_paste(
+    app: AppHandle,
+    pty_id: String,
+    data: String,
+    bracketed: Option<bool>,
+    safe_paste: Option<bool>,
+) -> Result<Option<String>, String> {
+    let started = Instant::now();
+    let bracketed = bracketed.unwrap_or(false);
+    let mut sessions = PTY_SESSIONS.lock().unwrap();
+    let session = sessions
+        .get_mut(&pty_id)
+        .ok_or_else(|| format!("PTY session {} not found", pty_id))?;
+
+    let hazards = if safe_paste.unwrap_or(false) {
+        scan(&data, bracketed)
+    } else {
+        Vec::new()
+    };
+    if hazards.is_empty() {
+        write_session(
+            &events::sink(&app),
+            &pty_id,
+            session,
+            paste_data(&data, bracketed),
+            started,
+        )?;
+        return Ok(None);
+    }
+
+    let paste_id = uuid::Uuid::new_v4().to_string();
+    warn!(
+        "Holding paste {} into PTY {} for confirmation: {:?}",
+        paste_id, pty_id, hazards
+    );
+    session.pending_paste = Some(PendingPaste {
+        paste_id: paste_id.clone(),
+        data,
+        bracketed,
+    });
+    drop(sessions);
+    events::emit(
+        &app,
+        PtyEvent::PasteWarning(PtyPasteWarning {
+            pty_id,
+            paste_id: paste_id.clone(),
+            hazards,
+        }),
+    );
+    Ok(Some(paste_id))
+}
+
+/// Write, strip and write, or drop a paste held back by `pty_paste`
+#[tauri::command]
+pub fn pty_paste_confirm(
+    app: AppHandle,
+    pty_id: String,
+    paste_id: String,
+    decision: PasteDecision,
+) -> Result<(), String> {
+    let started = Instant::now();
+    let mut sessions = PTY_SESSIONS.lock().unwrap();
+    let session = sessions
+        .get_mut(&pty_id)
+        .ok_or_else(|| format!("PTY session {} not found", pty_id))?;
+    let pending = match session.pending_paste.take() {
+        Some(pending) if pending.paste_id == paste_id => pending,
+        other => {
+            session.pending_paste = other;
+            return Err(format!("No pending paste {} for PTY {}", paste_id, pty_id));
+        }
+    };
+
+    info!(
+        "Paste {} into PTY {} confirmed: {:?}",
+        paste_id, pty_id, decision
+    );
+    let data = match decision {
+        PasteDecision::Paste => pending.data,
+        PasteDecision::Strip => strip(&pending.data, pending.bracketed),
+        PasteDecision::Cancel => return Ok(()),
+    };
+    write_session(
+        &events::sink(&app),
+        &pty_id,
+        session,
+        paste_data(&data, pending.bracketed),
+        started,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paste_data() {
+        assert_eq!(paste_data("a\nb\r\nc", false), "a\rb\rc");
+        assert_eq!(
+            paste_data("ls\x1b[201~rm -rf ~\n", true),
+            "\x1b[200~lsrm -rf ~\r\x1b[201~"
+        );
+        // Removing the inner marker must not leave an outer one behind
+        assert_eq!(
+            paste_data("ls\x1b[20\x1b[201~1~rm -rf ~\n", true),
+            "\x1b[200~lsrm -rf ~\r\x1b[201~"
+        );
+    }
+
+    #[test]
+    fn test_scan_flags_hazards() {
+        assert!(scan("git status", false).is_empty());
+        assert!(scan("echo a\techo b\nls", true).is_empty());
+        assert_eq!(scan("ls\n", false), vec![PasteHazard::LineBreak]);
+        assert_eq!(
+            scan("ls\x1b[201~rm -rf ~\n", true),
+            vec![PasteHazard::EscapeSequence]
+        );
+        assert_eq!(
+            scan("sleep 1\x03", true),
+            vec![PasteHazard::ControlCharacter]
+        );
+        assert_eq!(
+            scan("x\u{9b}31m", true),
+            vec![PasteHazard::ControlCharacter]
+        );
+    }
+
+    #[test]
+    fn test_strip_removes_hazards() {
+        assert_eq!(
+            strip("echo \x1b[31mhi\x1b[0m\x03\r\nls\n", false),
+            "echo hi ls "
+        );
+        assert_eq!(strip("a\x1b]0;title\x07b\nc", true), "ab\nc");
+        assert!(scan(&strip("ls\x1b[201~rm\x7f\n", false), false).is_empty());
+    }
+}
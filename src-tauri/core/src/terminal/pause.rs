@@ -0,0 +1,170 @@
+//! Pausing a session's output, e.g. for a background tab.
+//!
+//! While paused no `pty-output` is emitted; output already read waits in the
+//! emitter and is emitted in order on resume. What happens to the program
+//! depends on the mode:
+//! - `Strict` stops reading right away. The kernel's PTY buffer is small, so
+//!   the program soon blocks on its writes.
+//! - `Buffered` keeps reading, into scrollback and the emitter's queue, until
+//!   the buffer cap is reached, and only then stops. The program keeps running
+//!   for a while and resuming doesn't have to wait for it to catch up.
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Condvar, Mutex};
+
+/// Default output read while paused in `Buffered` mode before reading stops
+pub const DEFAULT_PAUSE_BUFFER_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PauseMode {
+    /// Stop reading; the program blocks once the kernel buffer is full
+    #[default]
+    Strict,
+    /// Keep reading up to the buffer cap before stopping
+    Buffered,
+}
+
+#[derive(Debug, Default)]
+struct PauseState {
+    mode: Option<PauseMode>,
+    /// Output read while paused allowed in `Buffered` mode
+    cap: usize,
+    /// Output read since the session was paused
+    buffered: usize,
+    /// Set when the session goes away so a paused read loop and emitter can finish
+    closed: bool,
+}
+
+/// Pause state of a session, shared with its read loop and emitter
+#[derive(Debug, Default)]
+pub struct PauseControl {
+    state: Mutex<PauseState>,
+    changed: Condvar,
+}
+
+impl PauseControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pause, or change the mode and cap of a pause in progress
+    pub fn pause(&self, mode: PauseMode, cap: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.mode = Some(mode);
+        state.cap = cap;
+        self.changed.notify_all();
+    }
+
+    /// Resume and return the output read while paused
+    pub fn resume(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.mode = None;
+        self.changed.notify_all();
+        std::mem::take(&mut state.buffered)
+    }
+
+    pub fn mode(&self) -> Option<PauseMode> {
+        self.state.lock().unwrap().mode
+    }
+
+    /// Block the read loop while paused and nothing more may be read
+    pub fn wait_for_reading(&self) {
+        let state = self.state.lock().unwrap();
+        let _state = self
+            .changed
+            .wait_while(state, |state| {
+                !state.closed
+                    && match state.mode {
+                        None => false,
+                        Some(PauseMode::Strict) => true,
+                        Some(PauseMode::Buffered) => state.buffered >= state.cap,
+                    }
+            })
+            .unwrap();
+    }
+
+    /// Record output read, counted against the cap while paused
+    pub fn read(&self, bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        if state.mode.is_some() {
+            state.buffered += bytes;
+        }
+    }
+
+    /// Block the emitter while paused
+    pub fn wait_for_emitting(&self) {
+        let state = self.state.lock().unwrap();
+        let _state = self
+            .changed
+            .wait_while(state, |state| !state.closed && state.mode.is_some())
+            .unwrap();
+    }
+
+    /// Release a paused read loop and emitter for good
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn blocked(pause: &Arc<PauseControl>, wait: fn(&PauseControl)) -> std::thread::JoinHandle<()> {
+        let pause = pause.clone();
+        let thread = std::thread::spawn(move || wait(&pause));
+        std::thread::sleep(Duration::from_millis(20));
+        thread
+    }
+
+    #[test]
+    fn test_strict_pause_stops_reading_at_once() {
+        let pause = Arc::new(PauseControl::new());
+        pause.pause(PauseMode::Strict, 0);
+        let reader = blocked(&pause, PauseControl::wait_for_reading);
+        let emitter = blocked(&pause, PauseControl::wait_for_emitting);
+        assert!(!reader.is_finished());
+        assert!(!emitter.is_finished());
+
+        assert_eq!(pause.resume(), 0);
+        reader.join().unwrap();
+        emitter.join().unwrap();
+    }
+
+    #[test]
+    fn test_buffered_pause_reads_up_to_cap() {
+        let pause = Arc::new(PauseControl::new());
+        pause.pause(PauseMode::Buffered, 100);
+        pause.read(60);
+        // Still below the cap
+        pause.wait_for_reading();
+        let emitter = blocked(&pause, PauseControl::wait_for_emitting);
+        assert!(!emitter.is_finished());
+
+        pause.read(60);
+        let reader = blocked(&pause, PauseControl::wait_for_reading);
+        assert!(!reader.is_finished());
+
+        assert_eq!(pause.resume(), 120);
+        reader.join().unwrap();
+        emitter.join().unwrap();
+        // Output read while running doesn't count
+        pause.read(10);
+        assert_eq!(pause.resume(), 0);
+    }
+
+    #[test]
+    fn test_close_releases_paused_threads() {
+        let pause = Arc::new(PauseControl::new());
+        pause.pause(PauseMode::Strict, 0);
+        let reader = blocked(&pause, PauseControl::wait_for_reading);
+        let emitter = blocked(&pause, PauseControl::wait_for_emitting);
+        pause.close();
+        reader.join().unwrap();
+        emitter.join().unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+//! Round trip through a live shell, for diagnosing input that feels slow.
+//!
+//! `pty_ping` types a command that prints a unique marker and times how long
+//! the marker takes to come back as output. This covers the whole path a
+//! keystroke takes: the write, the shell reading and running the command, and
+//! the read loop picking up what it printed. The marker is split in the typed
+//! command, so the shell's echo of the command itself doesn't count.
+//!
+//! The ping needs the shell at a prompt (Unix only) and is visible: the command
+//! and the marker show up in the terminal and its scrollback like anything
+//! typed. A leading space keeps the command out of the history of bash and zsh
+//! when they are set to ignore such lines. Input half typed at the prompt is
+//! sent along and spoils the ping.
+
+use super::events::{self, EventSink};
+use super::matcher::OutputMatcher;
+use super::{at_prompt, write_session, PTY_SESSIONS};
+use log::{info, warn};
+use std::time::{Duration, Instant};
+use tauri::AppHandle;
+use tokio::sync::broadcast;
+
+const MARKER_PREFIX: &str = "talkcody-ping-";
+
+/// Command that prints `MARKER_PREFIX` followed by `nonce`, without the two
+/// being next to each other in the command
+fn ping_command(nonce: &str) -> String {
+    format!(" printf '%s%s\\n' {} {}\r", MARKER_PREFIX, nonce)
+}
+
+async fn ping(sink: &EventSink, pty_id: &str, timeout: Duration) -> Result<u64, String> {
+    if !at_prompt(pty_id)? {
+        return Err(format!(
+            "PTY {} is running a command; it can only be pinged at a prompt",
+            pty_id
+        ));
+    }
+
+    let nonce = uuid::Uuid::new_v4().simple().to_string();
+    let mut matcher = OutputMatcher::new(&format!("{}{}", MARKER_PREFIX, nonce), false)?;
+    let started = Instant::now();
+    let mut output_rx = {
+        let mut sessions = PTY_SESSIONS.lock().unwrap();
+        let session = sessions
+            .get_mut(pty_id)
+            .ok_or_else(|| format!("PTY session {} not found", pty_id))?;
+        // Subscribed before writing so that a fast reply isn't missed
+        let output_rx = session.output_tx.subscribe();
+        write_session(sink, pty_id, session, ping_command(&nonce), started)?;
+        output_rx
+    };
+
+    let wait = async {
+        loop {
+            match output_rx.recv().await {
+                Ok(chunk) if matcher.feed(&chunk) => return Ok(started.elapsed()),
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("pty_ping on {} skipped {} output chunks", pty_id, skipped);
+                    matcher.reset();
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(format!("PTY {} closed before answering the ping", pty_id))
+                }
+            }
+        }
+    };
+
+    let elapsed = tokio::time::timeout(timeout, wait).await.map_err(|_| {
+        format!(
+            "PTY {} did not answer the ping within {}ms",
+            pty_id,
+            timeout.as_millis()
+        )
+    })??;
+    info!("PTY {} answered a ping in {:?}", pty_id, elapsed);
+    Ok(elapsed.as_micros().min(u64::MAX as u128) as u64)
+}
+
+/// Time in microseconds for a command typed into the session's shell to print
+/// its output, see the module docs. Fails if the shell isn't at a prompt or
+/// doesn't answer within `timeout_ms`.
+#[tauri::command]
+pub async fn pty_ping(app: AppHandle, pty_id: String, timeout_ms: u64) -> Result<u64, String> {
+    ping(
+        &events::sink(&app),
+        &pty_id,
+        Duration::from_millis(timeout_ms),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::events::CollectorSink;
+    use crate::terminal::{spawn_session, PtySpawnOptions};
+
+    #[test]
+    fn test_echoed_command_does_not_contain_marker() {
+        let command = ping_command("abc123");
+        assert!(!command.contains("talkcody-ping-abc123"));
+        assert!(command.starts_with(' '));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_ping_round_trips_through_shell() {
+        let sink: EventSink = CollectorSink::new();
+        let pty_id = spawn_session(
+            &sink,
+            None,
+            None,
+            None,
+            Some("/bin/sh".to_string()),
+            PtySpawnOptions::default(),
+        )
+        .unwrap();
+
+        let result = ping(&sink, &pty_id, Duration::from_secs(10)).await;
+        if let Some(mut session) = PTY_SESSIONS.lock().unwrap().remove(&pty_id) {
+            let _ = session.child.kill();
+        }
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(ping(&sink, &pty_id, Duration::from_secs(1)).await.is_err());
+    }
+}
@@ -0,0 +1,238 @@
+//! Spawning on Unix with setup run in the child between fork and exec.
+//!
+//! portable-pty offers no hook between fork and exec, so sessions that need
+//! one are started with `std::process::Command` on the PTY slave instead. Spawn
+//! options add steps to a [`PreExecChain`], which runs them in a single
+//! `pre_exec` closure in a fixed order, whatever order they were added in:
+//!
+//! 1. `setsid`, so the shell leads a new session
+//! 2. make the PTY (stdin) the controlling terminal, which needs the new session
+//! 3. `setrlimit`, while still privileged enough to set hard limits
+//! 4. drop privileges last: supplementary groups and gid, then uid, since
+//!    changing the gid needs the privileges the uid change gives up
+//!
+//! If a step fails the spawn fails; the shell never runs half set up.
+
+use log::info;
+use portable_pty::{CommandBuilder, MasterPty};
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+pub type LimitResource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+pub type LimitResource = libc::c_int;
+
+/// One step run in the child before exec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreExecAction {
+    NewSession,
+    ControllingTerminal,
+    SetLimit { resource: LimitResource, value: u64 },
+    SetIds { uid: libc::uid_t, gid: libc::gid_t },
+}
+
+impl PreExecAction {
+    /// Position in the chain; actions of the same stage keep the order they
+    /// were added in
+    fn stage(&self) -> u8 {
+        match self {
+            PreExecAction::NewSession => 0,
+            PreExecAction::ControllingTerminal => 1,
+            PreExecAction::SetLimit { .. } => 2,
+            PreExecAction::SetIds { .. } => 3,
+        }
+    }
+
+    /// Only async-signal-safe calls: this runs between fork and exec
+    unsafe fn apply(&self) -> io::Result<()> {
+        let result = match *self {
+            PreExecAction::NewSession => libc::setsid(),
+            PreExecAction::ControllingTerminal => libc::ioctl(0, libc::TIOCSCTTY as _, 0),
+            PreExecAction::SetLimit { resource, value } => {
+                let limit = libc::rlimit {
+                    rlim_cur: value as libc::rlim_t,
+                    rlim_max: value as libc::rlim_t,
+                };
+                libc::setrlimit(resource, &limit)
+            }
+            PreExecAction::SetIds { uid, gid } => {
+                // Only root can (and must) drop its supplementary groups
+                if libc::geteuid() == 0 && libc::setgroups(1, &gid) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                if libc::setgid(gid) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                libc::setuid(uid)
+            }
+        };
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Ordered setup for a spawned child
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PreExecChain {
+    actions: Vec<PreExecAction>,
+}
+
+impl PreExecChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an action at its place in the chain
+    pub fn push(&mut self, action: PreExecAction) -> &mut Self {
+        let at = self
+            .actions
+            .iter()
+            .position(|existing| existing.stage() > action.stage())
+            .unwrap_or(self.actions.len());
+        self.actions.insert(at, action);
+        self
+    }
+
+    pub fn actions(&self) -> &[PreExecAction] {
+        &self.actions
+    }
+
+    /// Install the chain as the `pre_exec` hook of `command`
+    pub fn install(self, command: &mut Command) {
+        // SAFETY: the closure runs between fork and exec and only makes
+        // async-signal-safe calls
+        unsafe {
+            command.pre_exec(move || {
+                for action in &self.actions {
+                    action.apply()?;
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+/// Start `cmd` on the PTY's slave side as the leader of a new session with the
+/// PTY as controlling terminal, after the actions of `chain`
+pub fn spawn(
+    master: &dyn MasterPty,
+    cmd: &CommandBuilder,
+    mut chain: PreExecChain,
+) -> Result<Box<dyn portable_pty::Child + Send + Sync>, String> {
+    let tty = master
+        .tty_name()
+        .ok_or_else(|| "Failed to find the PTY slave device".to_string())?;
+    let slave = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(&tty)
+        .map_err(|e| format!("Failed to open PTY slave {}: {}", tty.display(), e))?;
+    let stdio = || {
+        slave
+            .try_clone()
+            .map(Stdio::from)
+            .map_err(|e| format!("Failed to duplicate PTY slave: {}", e))
+    };
+
+    let argv = cmd.get_argv();
+    let program = argv
+        .first()
+        .ok_or_else(|| "No shell command to spawn".to_string())?;
+    let mut command = Command::new(program);
+    command
+        .args(&argv[1..])
+        .env_clear()
+        .envs(cmd.iter_full_env_as_str())
+        .stdin(stdio()?)
+        .stdout(stdio()?)
+        .stderr(stdio()?);
+    // portable-pty falls back to the home directory the same way
+    match cmd.get_cwd() {
+        Some(cwd) => {
+            command.current_dir(cwd);
+        }
+        None => {
+            if let Some(home) = dirs::home_dir() {
+                command.current_dir(home);
+            }
+        }
+    }
+
+    // What portable-pty does for every spawn
+    chain
+        .push(PreExecAction::NewSession)
+        .push(PreExecAction::ControllingTerminal);
+    info!("Spawning shell with pre-exec actions {:?}", chain.actions());
+    chain.install(&mut command);
+
+    let child = command
+        .spawn()
+        .map_err(|e| format!("Failed to spawn shell: {}", e))?;
+    Ok(Box::new(child))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_actions_run_in_stage_order() {
+        let ids = PreExecAction::SetIds { uid: 1, gid: 1 };
+        let limit = PreExecAction::SetLimit {
+            resource: libc::RLIMIT_NOFILE,
+            value: 64,
+        };
+        let mut chain = PreExecChain::new();
+        chain
+            .push(ids)
+            .push(limit)
+            .push(PreExecAction::ControllingTerminal)
+            .push(PreExecAction::NewSession);
+        assert_eq!(
+            chain.actions(),
+            &[
+                PreExecAction::NewSession,
+                PreExecAction::ControllingTerminal,
+                limit,
+                ids
+            ]
+        );
+    }
+
+    #[test]
+    fn test_setsid_limits_and_ids_combine() {
+        // SAFETY: getuid and getgid can't fail
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let mut chain = PreExecChain::new();
+        chain
+            .push(PreExecAction::SetIds { uid, gid })
+            .push(PreExecAction::SetLimit {
+                resource: libc::RLIMIT_NOFILE,
+                value: 64,
+            })
+            .push(PreExecAction::NewSession);
+
+        let mut command = Command::new("/bin/sh");
+        command
+            .args(["-c", "ulimit -n; id -u; sleep 1"])
+            .stdout(Stdio::piped());
+        chain.install(&mut command);
+        let child = command.spawn().unwrap();
+        let pid = child.id() as libc::pid_t;
+        // SAFETY: getsid has no memory safety requirements
+        let sid = unsafe { libc::getsid(pid) };
+        let output = child.wait_with_output().unwrap();
+
+        assert_eq!(sid, pid);
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(lines, ["64", &uid.to_string()]);
+    }
+}
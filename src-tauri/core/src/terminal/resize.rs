@@ -0,0 +1,125 @@
+//! Minimum-size policy for resizes while a full-screen program is running.
+
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_MIN_COLS: u16 = 20;
+const DEFAULT_MIN_ROWS: u16 = 5;
+
+/// What to do with a resize below the floor
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeFloorMode {
+    /// Keep the current size and ignore the request
+    Reject,
+    /// Apply the request with each dimension raised to the floor
+    #[default]
+    Clamp,
+}
+
+/// Smallest size applied while a full-screen (alternate screen) program is active.
+/// Some TUIs crash or misrender when shrunk to a sliver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResizeFloor {
+    #[serde(default = "default_min_cols")]
+    pub min_cols: u16,
+    #[serde(default = "default_min_rows")]
+    pub min_rows: u16,
+    #[serde(default)]
+    pub mode: ResizeFloorMode,
+}
+
+impl Default for ResizeFloor {
+    fn default() -> Self {
+        Self {
+            min_cols: DEFAULT_MIN_COLS,
+            min_rows: DEFAULT_MIN_ROWS,
+            mode: ResizeFloorMode::default(),
+        }
+    }
+}
+
+fn default_min_cols() -> u16 {
+    DEFAULT_MIN_COLS
+}
+
+fn default_min_rows() -> u16 {
+    DEFAULT_MIN_ROWS
+}
+
+/// Outcome of checking a resize request against the floor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeDecision {
+    Apply { cols: u16, rows: u16 },
+    Clamp { cols: u16, rows: u16 },
+    Reject,
+}
+
+impl ResizeFloor {
+    pub fn check(&self, cols: u16, rows: u16) -> ResizeDecision {
+        if cols >= self.min_cols && rows >= self.min_rows {
+            return ResizeDecision::Apply { cols, rows };
+        }
+        match self.mode {
+            ResizeFloorMode::Reject => ResizeDecision::Reject,
+            ResizeFloorMode::Clamp => ResizeDecision::Clamp {
+                cols: cols.max(self.min_cols),
+                rows: rows.max(self.min_rows),
+            },
+        }
+    }
+}
+
+/// Payload of the `pty-resize-rejected` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PtyResizeRejected {
+    pub pty_id: String,
+    pub requested_cols: u16,
+    pub requested_rows: u16,
+    pub min_cols: u16,
+    pub min_rows: u16,
+    pub mode: ResizeFloorMode,
+    /// Size actually applied when clamping; `None` when the request was refused
+    pub applied_cols: Option<u16>,
+    pub applied_rows: Option<u16>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resize_above_floor_is_applied() {
+        let floor = ResizeFloor::default();
+        assert_eq!(
+            floor.check(80, 24),
+            ResizeDecision::Apply { cols: 80, rows: 24 }
+        );
+        assert_eq!(
+            floor.check(20, 5),
+            ResizeDecision::Apply { cols: 20, rows: 5 }
+        );
+    }
+
+    #[test]
+    fn test_resize_below_floor_is_clamped_or_rejected() {
+        let clamp = ResizeFloor::default();
+        assert_eq!(
+            clamp.check(3, 40),
+            ResizeDecision::Clamp { cols: 20, rows: 40 }
+        );
+
+        let reject = ResizeFloor {
+            mode: ResizeFloorMode::Reject,
+            ..Default::default()
+        };
+        assert_eq!(reject.check(80, 2), ResizeDecision::Reject);
+    }
+
+    #[test]
+    fn test_resize_floor_deserializes_with_defaults() {
+        let floor: ResizeFloor = serde_json::from_str(r#"{"mode":"reject"}"#).unwrap();
+        assert_eq!(floor.min_cols, 20);
+        assert_eq!(floor.min_rows, 5);
+        assert_eq!(floor.mode, ResizeFloorMode::Reject);
+    }
+}
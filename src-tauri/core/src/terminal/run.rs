@@ -0,0 +1,164 @@
+//! One-off commands run in their own session, streamed like any other output.
+
+use super::events::{self, EventSink, PtyEvent, PtyEventSink};
+use super::{open_session, start_session, PtySession, PtySpawnOptions, PtySpawnResult};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+
+/// Payload of the `pty-command-result` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PtyCommandResult {
+    pub pty_id: String,
+    /// `None` if the exit status couldn't be read
+    pub exit_code: Option<i32>,
+}
+
+/// Wait for the exited shell and report its exit code. Called by the read loop
+/// after the session was removed, before `pty-close`.
+pub(super) fn emit_command_result(sink: &dyn PtyEventSink, pty_id: &str, session: &mut PtySession) {
+    let exit_code = match session.child.wait() {
+        Ok(status) => Some(status.exit_code() as i32),
+        Err(e) => {
+            warn!("Failed to read exit status of PTY {}: {}", pty_id, e);
+            None
+        }
+    };
+    info!("Command in PTY {} exited with {:?}", pty_id, exit_code);
+    events::emit(
+        sink,
+        PtyEvent::CommandResult(PtyCommandResult {
+            pty_id: pty_id.to_string(),
+            exit_code,
+        }),
+    );
+}
+
+/// Run `cmd` through the default shell in a new session and return its id
+/// right away. Output streams as `pty-output`; when the command exits,
+/// `pty-command-result` carries the exit code and the session closes itself
+/// (`pty-close`). Killing the session with `pty_kill` skips the result.
+///
+/// With `answer_cursor_queries`, cursor position requests (`ESC [ 6 n`) are
+/// answered by the backend so programs that probe the cursor don't hang when
+/// no terminal is attached. The reported position is approximate; the
+/// frontend must not answer them too. `auto_respond_size` does the same for
+/// window size queries (see [`PtySpawnOptions::auto_respond_size`]).
+#[tauri::command]
+pub fn pty_run_stream(
+    app: AppHandle,
+    cmd: String,
+    cwd: Option<String>,
+    answer_cursor_queries: Option<bool>,
+    auto_respond_size: Option<bool>,
+) -> Result<PtySpawnResult, String> {
+    let pty_id = run_stream(
+        &events::sink(&app),
+        &cmd,
+        cwd,
+        None,
+        answer_cursor_queries.unwrap_or(false),
+        auto_respond_size.unwrap_or(false),
+    )?;
+    Ok(PtySpawnResult {
+        pty_id,
+        ready: None,
+    })
+}
+
+/// Start `cmd` in a new session sending its events to `sink`
+fn run_stream(
+    sink: &EventSink,
+    cmd: &str,
+    cwd: Option<String>,
+    shell: Option<String>,
+    answer_cursor_queries: bool,
+    auto_respond_size: bool,
+) -> Result<String, String> {
+    if cmd.trim().is_empty() {
+        return Err("Command must not be empty".to_string());
+    }
+
+    let options = PtySpawnOptions {
+        auto_respond_size,
+        ..Default::default()
+    };
+    let (mut session, reader) = open_session(cwd, None, None, shell, &options, Some(cmd))?;
+    session.report_exit = true;
+    session.output.lock().unwrap().answer_cursor_queries = answer_cursor_queries;
+
+    let pty_id = uuid::Uuid::new_v4().to_string();
+    info!("Running command in PTY {}: {}", pty_id, cmd);
+    start_session(sink, &pty_id, session, reader);
+    Ok(pty_id)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::terminal::events::CollectorSink;
+    use std::io::Read;
+    use std::time::Duration;
+
+    #[test]
+    fn test_command_runs_and_reports_exit_code() {
+        let options = PtySpawnOptions::default();
+        let (mut session, mut reader) = open_session(
+            None,
+            None,
+            None,
+            Some("/bin/sh".to_string()),
+            &options,
+            Some("echo talkcody-run; exit 3"),
+        )
+        .unwrap();
+
+        let mut output = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !String::from_utf8_lossy(&output).contains("talkcody-run") {
+            match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => output.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert!(String::from_utf8_lossy(&output).contains("talkcody-run"));
+        assert_eq!(session.child.wait().unwrap().exit_code(), 3);
+    }
+
+    #[test]
+    fn test_streamed_command_emits_output_result_and_close() {
+        let collector = CollectorSink::new();
+        let sink: EventSink = collector.clone();
+        let pty_id = run_stream(
+            &sink,
+            "echo talkcody-stream; exit 4",
+            None,
+            Some("/bin/sh".to_string()),
+            false,
+            false,
+        )
+        .unwrap();
+
+        assert!(collector.wait_for(Duration::from_secs(10), |event| {
+            matches!(event, PtyEvent::Close(close) if close.pty_id == pty_id)
+        }));
+        let events = collector.events();
+        let output: String = events
+            .iter()
+            .filter_map(|event| match event {
+                PtyEvent::Output(output) if output.pty_id == pty_id => Some(output.data.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert!(output.contains("talkcody-stream"));
+        // The result comes after all output and right before the close
+        let result = events.iter().rposition(|event| {
+            matches!(event, PtyEvent::CommandResult(result) if result.pty_id == pty_id && result.exit_code == Some(4))
+        });
+        let last_output = events.iter().rposition(
+            |event| matches!(event, PtyEvent::Output(output) if output.pty_id == pty_id),
+        );
+        assert!(result.is_some() && last_output < result);
+        assert!(matches!(events.last(), Some(PtyEvent::Close(_))));
+    }
+}
@@ -0,0 +1,184 @@
+//! Counters of the work the read loops and emitters do, for finding the
+//! session behind battery drain or CPU use. Each read or emit is one relaxed
+//! atomic increment; rates are only computed when `pty_runtime_stats` is
+//! called.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Read loop and emitter activity of one session
+#[derive(Debug)]
+pub struct Activity {
+    reads: AtomicU64,
+    read_bytes: AtomicU64,
+    emits: AtomicU64,
+    /// Time and wakeup count of the previous sample
+    sampled: Mutex<(Instant, u64)>,
+}
+
+/// Activity of one session, reported by `pty_runtime_stats`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionRuntimeStats {
+    pub pty_id: String,
+    pub name: Option<String>,
+    /// Reads that returned output
+    pub read_iterations: u64,
+    pub read_bytes: u64,
+    /// `pty-output` events emitted
+    pub emits: u64,
+    /// Events of all types emitted, see `pty_event_counts`
+    pub events: u64,
+    /// Reads plus emits per second since the previous `pty_runtime_stats`
+    /// call, or since the session started
+    pub wakeups_per_sec: f64,
+    /// Whether `pty-output` events are emitted; turning them off with
+    /// `pty_set_emit_enabled` saves the emits of a busy background session
+    pub emit_enabled: bool,
+}
+
+/// Activity of all sessions, busiest first
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PtyRuntimeStats {
+    pub read_iterations: u64,
+    pub read_bytes: u64,
+    pub emits: u64,
+    pub events: u64,
+    pub wakeups_per_sec: f64,
+    pub sessions: Vec<SessionRuntimeStats>,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Self {
+            reads: AtomicU64::new(0),
+            read_bytes: AtomicU64::new(0),
+            emits: AtomicU64::new(0),
+            sampled: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    pub fn read(&self, bytes: usize) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.read_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn emit(&self) {
+        self.emits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counters of the session, starting a new rate interval
+    pub fn sample(
+        &self,
+        pty_id: &str,
+        name: Option<String>,
+        emit_enabled: bool,
+        events: u64,
+    ) -> SessionRuntimeStats {
+        self.sample_at(Instant::now(), pty_id, name, emit_enabled, events)
+    }
+
+    fn sample_at(
+        &self,
+        now: Instant,
+        pty_id: &str,
+        name: Option<String>,
+        emit_enabled: bool,
+        events: u64,
+    ) -> SessionRuntimeStats {
+        let read_iterations = self.reads.load(Ordering::Relaxed);
+        let emits = self.emits.load(Ordering::Relaxed);
+        let wakeups = read_iterations + emits;
+
+        let mut sampled = self.sampled.lock().unwrap();
+        let elapsed = now.saturating_duration_since(sampled.0).as_secs_f64();
+        let wakeups_per_sec = if elapsed > 0.0 {
+            wakeups.saturating_sub(sampled.1) as f64 / elapsed
+        } else {
+            0.0
+        };
+        *sampled = (now, wakeups);
+
+        SessionRuntimeStats {
+            pty_id: pty_id.to_string(),
+            name,
+            read_iterations,
+            read_bytes: self.read_bytes.load(Ordering::Relaxed),
+            emits,
+            events,
+            wakeups_per_sec,
+            emit_enabled,
+        }
+    }
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PtyRuntimeStats {
+    pub fn from_sessions(mut sessions: Vec<SessionRuntimeStats>) -> Self {
+        sessions.sort_by(|a, b| b.wakeups_per_sec.total_cmp(&a.wakeups_per_sec));
+        Self {
+            read_iterations: sessions.iter().map(|s| s.read_iterations).sum(),
+            read_bytes: sessions.iter().map(|s| s.read_bytes).sum(),
+            emits: sessions.iter().map(|s| s.emits).sum(),
+            events: sessions.iter().map(|s| s.events).sum(),
+            wakeups_per_sec: sessions.iter().map(|s| s.wakeups_per_sec).sum(),
+            sessions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_wakeup_rate_covers_interval_since_last_sample() {
+        let activity = Activity::new();
+        let start = activity.sampled.lock().unwrap().0;
+        for _ in 0..10 {
+            activity.read(100);
+        }
+        activity.emit();
+        activity.emit();
+
+        let stats = activity.sample_at(start + Duration::from_secs(2), "a", None, true, 0);
+        assert_eq!(stats.read_iterations, 10);
+        assert_eq!(stats.read_bytes, 1000);
+        assert_eq!(stats.emits, 2);
+        assert_eq!(stats.wakeups_per_sec, 6.0);
+
+        // Only the wakeups since the previous sample count
+        activity.read(1);
+        let stats = activity.sample_at(start + Duration::from_secs(3), "a", None, true, 0);
+        assert_eq!(stats.read_iterations, 11);
+        assert_eq!(stats.wakeups_per_sec, 1.0);
+    }
+
+    #[test]
+    fn test_runtime_stats_sorts_busiest_first() {
+        let session = |pty_id: &str, wakeups_per_sec: f64| SessionRuntimeStats {
+            pty_id: pty_id.to_string(),
+            name: None,
+            read_iterations: 5,
+            read_bytes: 50,
+            emits: 1,
+            events: 3,
+            wakeups_per_sec,
+            emit_enabled: true,
+        };
+        let stats =
+            PtyRuntimeStats::from_sessions(vec![session("idle", 0.5), session("tail", 40.0)]);
+        assert_eq!(stats.sessions[0].pty_id, "tail");
+        assert_eq!(stats.read_iterations, 10);
+        assert_eq!(stats.emits, 2);
+        assert_eq!(stats.events, 6);
+        assert_eq!(stats.wakeups_per_sec, 40.5);
+    }
+}
@@ -0,0 +1,643 @@
+//! Bounded per-session buffer of raw PTY output.
+//!
+//! With compression on, output older than the most recent 256 KiB is deflated
+//! in blocks of 64 KiB. Verbose logs typically shrink 5-10x; reading the
+//! history decompresses it on the fly.
+//!
+//! Appends are stamped with the wall-clock time so that the history can also be
+//! read from a point in time, at a resolution of [`TIME_MARK_RESOLUTION_MS`].
+//!
+//! History can also be limited to a number of lines, for frontends that page
+//! it by lines. It is then trimmed at line starts, except that compressed
+//! history is dropped a block at a time like with the byte cap.
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Default scrollback cap per session
+pub const DEFAULT_SCROLLBACK_BYTES: usize = 1024 * 1024;
+
+/// Caps of all sessions together may not exceed this when changed at runtime
+pub const SCROLLBACK_BUDGET_BYTES: usize = 512 * 1024 * 1024;
+
+/// Appends smaller than this are merged into the previous chunk
+const MIN_CHUNK_BYTES: usize = 4096;
+
+/// Most recent output kept uncompressed when compression is on
+const RECENT_BYTES: usize = 256 * 1024;
+
+/// Older output is compressed in blocks of at least this size
+const BLOCK_BYTES: usize = 64 * 1024;
+
+/// Appends within this long of the last time mark share it
+pub const TIME_MARK_RESOLUTION_MS: u64 = 100;
+
+/// Which limits trim the scrollback
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrollbackLimit {
+    /// The byte cap only
+    #[default]
+    Bytes,
+    /// The line limit only. History still never exceeds
+    /// [`SCROLLBACK_BUDGET_BYTES`].
+    Lines,
+    /// Whichever of the byte cap and line limit retains less
+    Both,
+}
+
+#[derive(Debug)]
+struct CompressedBlock {
+    data: Vec<u8>,
+    /// Uncompressed size
+    len: usize,
+}
+
+/// Raw output history, trimmed from the front once it exceeds its byte cap
+#[derive(Debug)]
+pub struct Scrollback {
+    /// Compressed older history, preceding `chunks`
+    blocks: VecDeque<CompressedBlock>,
+    chunks: VecDeque<Vec<u8>>,
+    /// Bytes in `chunks`
+    raw_len: usize,
+    len: usize,
+    cap: usize,
+    compress: bool,
+    /// Bytes ever appended, so that time marks can refer to absolute offsets
+    total: u64,
+    /// (absolute offset, Unix time in ms) of the first byte appended at each time.
+    /// The front mark may precede the retained history.
+    time_marks: VecDeque<(u64, u64)>,
+    /// Time the most recently trimmed byte was appended
+    trimmed_ms: Option<u64>,
+    limit: ScrollbackLimit,
+    max_lines: Option<usize>,
+    /// Absolute offsets just past each retained line break, tracked only with
+    /// a line limit
+    line_ends: VecDeque<u64>,
+}
+
+/// History read from a point in time with [`Scrollback::since`]
+#[derive(Debug, Default)]
+pub struct ScrollbackSince {
+    pub data: Vec<u8>,
+    /// Whether output appended at or after the requested time was already trimmed
+    pub truncated: bool,
+}
+
+impl Scrollback {
+    pub fn new(cap: usize) -> Self {
+        Self {
+            blocks: VecDeque::new(),
+            chunks: VecDeque::new(),
+            raw_len: 0,
+            len: 0,
+            cap,
+            compress: false,
+            total: 0,
+            time_marks: VecDeque::new(),
+            trimmed_ms: None,
+            limit: ScrollbackLimit::Bytes,
+            max_lines: None,
+            line_ends: VecDeque::new(),
+        }
+    }
+
+    /// Compress older history. Compressed history is dropped a block at a
+    /// time, so up to one block less than the cap may be retained.
+    pub fn with_compression(mut self) -> Self {
+        self.compress = true;
+        self
+    }
+
+    /// Keep at most `max_lines` lines, with or instead of the byte cap
+    /// depending on `limit`. A line limit with [`ScrollbackLimit::Bytes`] is
+    /// ignored.
+    pub fn with_max_lines(mut self, max_lines: usize, limit: ScrollbackLimit) -> Self {
+        self.limit = limit;
+        self.max_lines = (limit != ScrollbackLimit::Bytes).then_some(max_lines);
+        self
+    }
+
+    pub fn append(&mut self, data: &[u8]) {
+        self.append_at(data, now_ms());
+    }
+
+    fn append_at(&mut self, data: &[u8], now_ms: u64) {
+        if data.is_empty() {
+            return;
+        }
+
+        match self.time_marks.back() {
+            Some(&(_, ms)) if now_ms.saturating_sub(ms) < TIME_MARK_RESOLUTION_MS => {}
+            _ => self.time_marks.push_back((self.total, now_ms)),
+        }
+        if self.max_lines.is_some() {
+            let offset = self.total;
+            self.line_ends.extend(
+                data.iter()
+                    .enumerate()
+                    .filter(|(_, &byte)| byte == b'\n')
+                    .map(|(i, _)| offset + i as u64 + 1),
+            );
+        }
+        self.total += data.len() as u64;
+
+        match self.chunks.back_mut() {
+            Some(last) if last.len() < MIN_CHUNK_BYTES => last.extend_from_slice(data),
+            _ => self.chunks.push_back(data.to_vec()),
+        }
+        self.raw_len += data.len();
+        self.len += data.len();
+        self.trim();
+        self.compress_old();
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn cap(&self) -> usize {
+        self.cap
+    }
+
+    /// Bytes ever appended, i.e. the absolute offset of the end of the history
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn limit(&self) -> ScrollbackLimit {
+        self.limit
+    }
+
+    pub fn max_lines(&self) -> Option<usize> {
+        self.max_lines
+    }
+
+    /// Lines retained, counting a final line without a line break; only known
+    /// with a line limit
+    pub fn lines(&self) -> Option<usize> {
+        self.max_lines?;
+        let unterminated = match self.line_ends.back() {
+            Some(&end) => end < self.total,
+            None => self.len > 0,
+        };
+        Some(self.line_ends.len() + unterminated as usize)
+    }
+
+    /// Change the cap; a smaller cap drops the oldest history right away
+    pub fn set_cap(&mut self, cap: usize) {
+        self.cap = cap;
+        self.trim();
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.compress
+    }
+
+    /// Bytes held in memory, less than [`Scrollback::len`] when compressed
+    pub fn memory_bytes(&self) -> usize {
+        self.blocks
+            .iter()
+            .map(|block| block.data.len())
+            .sum::<usize>()
+            + self.raw_len
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.chunks.clear();
+        self.raw_len = 0;
+        self.len = 0;
+        self.time_marks.clear();
+        self.trimmed_ms = None;
+        self.line_ends.clear();
+    }
+
+    /// Copy of the retained history
+    pub fn contents(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.len);
+        for block in &self.blocks {
+            if let Err(e) = DeflateDecoder::new(&block.data[..]).read_to_end(&mut out) {
+                error!("Failed to decompress scrollback block: {}", e);
+            }
+        }
+        for chunk in &self.chunks {
+            out.extend_from_slice(chunk);
+        }
+        out
+    }
+
+    /// Retained history appended at or after `since_ms` (Unix time in ms).
+    /// Bytes appended within [`TIME_MARK_RESOLUTION_MS`] before it may be included.
+    pub fn since(&self, since_ms: u64) -> ScrollbackSince {
+        // A mark also covers appends made up to the resolution after it
+        let covers = |ms: u64| ms + TIME_MARK_RESOLUTION_MS > since_ms;
+        let truncated = self.trimmed_ms.is_some_and(covers);
+        let Some(&(offset, _)) = self.time_marks.iter().find(|(_, ms)| covers(*ms)) else {
+            return ScrollbackSince {
+                data: Vec::new(),
+                truncated,
+            };
+        };
+        let start = self.total - self.len as u64;
+        let skip = offset.saturating_sub(start) as usize;
+        let mut data = self.contents();
+        data.drain(..skip.min(data.len()));
+        ScrollbackSince { data, truncated }
+    }
+
+    /// Retained history between two absolute offsets, or `None` if its start
+    /// was already trimmed
+    pub fn range(&self, start: u64, end: u64) -> Option<Vec<u8>> {
+        let first = self.total - self.len as u64;
+        if start < first {
+            return None;
+        }
+        let end = end.min(self.total);
+        let start = start.min(end);
+        let mut data = self.contents();
+        data.truncate((end - first) as usize);
+        data.drain(..(start - first) as usize);
+        Some(data)
+    }
+
+    /// Drop the time marks of trimmed history, keeping the one that covers the
+    /// first retained byte
+    fn trim_time_marks(&mut self) {
+        let start = self.total - self.len as u64;
+        while let Some(&(offset, ms)) = self.time_marks.front() {
+            if offset >= start {
+                break;
+            }
+            self.trimmed_ms = Some(ms);
+            match self.time_marks.get(1) {
+                Some(&(next, _)) if next <= start => {
+                    self.time_marks.pop_front();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Drop the line ends of trimmed history
+    fn trim_line_ends(&mut self) {
+        let start = self.total - self.len as u64;
+        while self.line_ends.front().is_some_and(|&end| end <= start) {
+            self.line_ends.pop_front();
+        }
+    }
+
+    /// Drop the oldest bytes and lines until the buffer fits its limits
+    fn trim(&mut self) {
+        let cap = match self.limit {
+            ScrollbackLimit::Lines => SCROLLBACK_BUDGET_BYTES,
+            ScrollbackLimit::Bytes | ScrollbackLimit::Both => self.cap,
+        };
+        if self.len > cap {
+            self.drop_front(self.len - cap);
+            self.trim_line_ends();
+        }
+
+        if let (Some(max_lines), Some(lines)) = (self.max_lines, self.lines()) {
+            if max_lines == 0 {
+                self.drop_front(self.len);
+                self.trim_line_ends();
+            } else if lines > max_lines {
+                // Keep the history from the start of the first line to retain
+                let keep_from = self.line_ends[lines - max_lines - 1];
+                let start = self.total - self.len as u64;
+                self.drop_front((keep_from - start) as usize);
+                self.trim_line_ends();
+            }
+        }
+        self.trim_time_marks();
+    }
+
+    /// Drop the oldest `bytes`, or whole compressed blocks covering them
+    fn drop_front(&mut self, bytes: usize) {
+        let keep = self.len.saturating_sub(bytes);
+        while self.len > keep {
+            if let Some(block) = self.blocks.pop_front() {
+                self.len -= block.len;
+                continue;
+            }
+
+            let excess = self.len - keep;
+            let Some(front) = self.chunks.front_mut() else {
+                break;
+            };
+
+            if front.len() <= excess {
+                self.raw_len -= front.len();
+                self.len -= front.len();
+                self.chunks.pop_front();
+                continue;
+            }
+
+            // Don't start the retained history in the middle of a UTF-8 character
+            let mut cut = excess;
+            while cut < front.len() && (front[cut] & 0xc0) == 0x80 {
+                cut += 1;
+            }
+            front.drain(..cut);
+            self.raw_len -= cut;
+            self.len -= cut;
+        }
+    }
+
+    /// Compress the oldest uncompressed output beyond the recent window
+    fn compress_old(&mut self) {
+        if !self.compress {
+            return;
+        }
+        while self.raw_len >= RECENT_BYTES + BLOCK_BYTES {
+            let mut block = Vec::with_capacity(BLOCK_BYTES);
+            while block.len() < BLOCK_BYTES {
+                let Some(chunk) = self.chunks.pop_front() else {
+                    break;
+                };
+                block.extend_from_slice(&chunk);
+            }
+            self.raw_len -= block.len();
+
+            match deflate(&block) {
+                Ok(data) => self.blocks.push_back(CompressedBlock {
+                    data,
+                    len: block.len(),
+                }),
+                Err(e) => {
+                    // Keep it uncompressed rather than losing history
+                    warn!("Failed to compress scrollback block: {}", e);
+                    self.raw_len += block.len();
+                    self.chunks.push_front(block);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+fn deflate(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Check a new cap for a session against the budget left by the caps of the
+/// other sessions. Fails under [`ScrollbackLimit::Lines`], where the cap
+/// wouldn't trim anything.
+pub fn validate_cap(cap: usize, limit: ScrollbackLimit, other_caps: usize) -> Result<(), String> {
+    if limit == ScrollbackLimit::Lines {
+        return Err(
+            "Scrollback is limited by lines only; a byte cap has no effect on it".to_string(),
+        );
+    }
+    if cap == 0 {
+        return Err("Scrollback cap must be at least 1 byte".to_string());
+    }
+    let available = SCROLLBACK_BUDGET_BYTES.saturating_sub(other_caps);
+    if cap > available {
+        return Err(format!(
+            "Scrollback cap of {} bytes exceeds the {} bytes left in the budget of {} bytes",
+            cap, available, SCROLLBACK_BUDGET_BYTES
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrollback_trims_oldest_bytes() {
+        let mut scrollback = Scrollback::new(10);
+        scrollback.append(b"0123456789");
+        scrollback.append(b"abcde");
+        assert_eq!(scrollback.len(), 10);
+        assert_eq!(scrollback.contents(), b"56789abcde");
+    }
+
+    #[test]
+    fn test_scrollback_trim_keeps_utf8_boundary() {
+        let mut scrollback = Scrollback::new(3);
+        // "€" is three bytes; cutting two would leave a dangling continuation byte
+        scrollback.append("€ab".as_bytes());
+        assert_eq!(String::from_utf8(scrollback.contents()).unwrap(), "ab");
+    }
+
+    #[test]
+    fn test_scrollback_since_time() {
+        let mut scrollback = Scrollback::new(1024);
+        scrollback.append_at(b"old ", 1_000);
+        scrollback.append_at(b"older ", 1_050);
+        scrollback.append_at(b"new ", 5_000);
+        scrollback.append_at(b"newer", 9_000);
+
+        let since = scrollback.since(5_000);
+        assert_eq!(since.data, b"new newer");
+        assert!(!since.truncated);
+        assert_eq!(scrollback.since(0).data, b"old older new newer");
+        assert!(scrollback.since(10_000).data.is_empty());
+    }
+
+    #[test]
+    fn test_scrollback_since_time_reports_truncation() {
+        let mut scrollback = Scrollback::new(8);
+        scrollback.append_at(b"aaaa", 1_000);
+        scrollback.append_at(b"bbbb", 2_000);
+        scrollback.append_at(b"cccc", 3_000);
+
+        // "aaaa" was trimmed; asking for it clamps to what's left
+        let since = scrollback.since(1_000);
+        assert_eq!(since.data, b"bbbbcccc");
+        assert!(since.truncated);
+
+        let since = scrollback.since(2_000);
+        assert_eq!(since.data, b"bbbbcccc");
+        assert!(!since.truncated);
+
+        // Partly trimmed output keeps its time
+        scrollback.append_at(b"dd", 4_000);
+        let since = scrollback.since(2_000);
+        assert_eq!(since.data, b"bbccccdd");
+        assert!(since.truncated);
+    }
+
+    #[test]
+    fn test_set_cap_trims_oldest_when_shrinking() {
+        let mut scrollback = Scrollback::new(16);
+        scrollback.append(b"0123456789abcdef");
+        scrollback.set_cap(32);
+        assert_eq!(scrollback.contents(), b"0123456789abcdef");
+
+        scrollback.set_cap(4);
+        assert_eq!(scrollback.contents(), b"cdef");
+        scrollback.append(b"gh");
+        assert_eq!(scrollback.contents(), b"efgh");
+    }
+
+    #[test]
+    fn test_line_limit_trims_whole_lines() {
+        let mut scrollback = Scrollback::new(1024).with_max_lines(2, ScrollbackLimit::Both);
+        scrollback.append(b"one\ntwo");
+        assert_eq!(scrollback.lines(), Some(2));
+        scrollback.append(b"\nthr");
+        assert_eq!(scrollback.contents(), b"two\nthr");
+        scrollback.append(b"ee\n");
+        assert_eq!(scrollback.contents(), b"two\nthree\n");
+        scrollback.append(b"four");
+        assert_eq!(scrollback.contents(), b"three\nfour");
+        assert_eq!(scrollback.lines(), Some(2));
+    }
+
+    #[test]
+    fn test_line_limit_of_zero_keeps_nothing() {
+        let mut scrollback = Scrollback::new(1024).with_max_lines(0, ScrollbackLimit::Lines);
+        scrollback.append(b"$ ");
+        assert!(scrollback.contents().is_empty());
+        scrollback.append(b"ls\na.txt");
+        assert!(scrollback.contents().is_empty());
+        assert_eq!(scrollback.lines(), Some(0));
+    }
+
+    #[test]
+    fn test_scrollback_limit_policies() {
+        let long_lines = b"aaaaaaaaaa\nbbbbbbbbbb\ncccccccccc\n";
+
+        // Both: the byte cap cuts into a line, the line limit is already met
+        let mut both = Scrollback::new(15).with_max_lines(2, ScrollbackLimit::Both);
+        both.append(long_lines);
+        assert_eq!(both.contents(), b"bbb\ncccccccccc\n");
+        assert_eq!(both.lines(), Some(2));
+
+        let mut lines = Scrollback::new(15).with_max_lines(2, ScrollbackLimit::Lines);
+        lines.append(long_lines);
+        assert_eq!(lines.contents(), b"bbbbbbbbbb\ncccccccccc\n");
+
+        let mut bytes = Scrollback::new(15).with_max_lines(1, ScrollbackLimit::Bytes);
+        bytes.append(long_lines);
+        assert_eq!(bytes.contents(), b"bbb\ncccccccccc\n");
+        assert_eq!(bytes.lines(), None);
+    }
+
+    #[test]
+    fn test_range_by_absolute_offsets() {
+        let mut scrollback = Scrollback::new(8);
+        scrollback.append(b"abcdef");
+        assert_eq!(scrollback.range(2, 4).unwrap(), b"cd");
+        assert_eq!(scrollback.range(4, 100).unwrap(), b"ef");
+
+        scrollback.append(b"ghij");
+        assert_eq!(scrollback.total(), 10);
+        assert_eq!(scrollback.range(1, 4), None);
+        assert_eq!(scrollback.range(2, 4).unwrap(), b"cd");
+        assert!(scrollback.range(10, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_validate_cap_against_budget() {
+        let limit = ScrollbackLimit::Bytes;
+        assert!(validate_cap(DEFAULT_SCROLLBACK_BYTES, limit, 0).is_ok());
+        assert!(validate_cap(0, limit, 0).is_err());
+        assert!(validate_cap(SCROLLBACK_BUDGET_BYTES, limit, 0).is_ok());
+        assert!(validate_cap(SCROLLBACK_BUDGET_BYTES, limit, 1).is_err());
+    }
+
+    #[test]
+    fn test_validate_cap_rejects_line_limit() {
+        assert!(validate_cap(1024, ScrollbackLimit::Both, 0).is_ok());
+        assert!(validate_cap(1024, ScrollbackLimit::Lines, 0).is_err());
+    }
+
+    /// Verbose, repetitive log output like a build or server log
+    fn log_output(bytes: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(bytes + 128);
+        let mut i: u64 = 0;
+        while out.len() < bytes {
+            out.extend_from_slice(
+                format!(
+                    "2024-05-01T12:{:02}:{:02}Z INFO request id={} path=/api/v1/items/{} status=200 elapsed={}ms\r\n",
+                    i / 60 % 60,
+                    i % 60,
+                    i * 7919 % 100_000,
+                    i % 1000,
+                    i % 97
+                )
+                .as_bytes(),
+            );
+            i += 1;
+        }
+        out
+    }
+
+    #[test]
+    fn test_compressed_scrollback_round_trips() {
+        let log = log_output(2 * 1024 * 1024);
+        let mut scrollback = Scrollback::new(4 * 1024 * 1024).with_compression();
+        for chunk in log.chunks(8192) {
+            scrollback.append(chunk);
+        }
+        assert_eq!(scrollback.len(), log.len());
+        assert_eq!(scrollback.contents(), log);
+        assert!(scrollback.memory_bytes() < log.len() / 2);
+    }
+
+    #[test]
+    fn test_compressed_scrollback_trims_whole_blocks() {
+        let cap = 512 * 1024;
+        let log = log_output(2 * 1024 * 1024);
+        let mut scrollback = Scrollback::new(cap).with_compression();
+        for chunk in log.chunks(8192) {
+            scrollback.append(chunk);
+        }
+        let contents = scrollback.contents();
+        assert_eq!(contents.len(), scrollback.len());
+        assert!(log.ends_with(&contents));
+        assert!(scrollback.len() <= cap);
+        assert!(scrollback.len() >= cap - BLOCK_BYTES - 8192);
+    }
+
+    /// Memory used by 50 MB of log output, with and without compression.
+    /// Run with `cargo test -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn measure_compression_savings_for_50mb_log() {
+        let log = log_output(50 * 1024 * 1024);
+        for compress in [false, true] {
+            let mut scrollback = Scrollback::new(64 * 1024 * 1024);
+            if compress {
+                scrollback = scrollback.with_compression();
+            }
+            let started = std::time::Instant::now();
+            for chunk in log.chunks(8192) {
+                scrollback.append(chunk);
+            }
+            println!(
+                "compress={}: {} bytes retained in {} bytes of memory, appended in {:?}",
+                compress,
+                scrollback.len(),
+                scrollback.memory_bytes(),
+                started.elapsed()
+            );
+        }
+    }
+}
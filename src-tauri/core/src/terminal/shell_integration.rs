@@ -0,0 +1,341 @@
+//! Shell integration: command boundary markers (OSC 133) and the startup
+//! scripts that make bash and zsh emit them.
+//!
+//! The shell prints `OSC 133;A` when a prompt starts, `OSC 133;C` right before
+//! a command runs and `OSC 133;D;<exit>` when it finishes, which gives reliable
+//! command boundaries without guessing at prompts.
+
+use log::{info, warn};
+use portable_pty::CommandBuilder;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use tokio::sync::oneshot;
+
+/// Largest command output kept by `pty_capture_next`; anything beyond is dropped
+const MAX_CAPTURE_BYTES: usize = 4 * 1024 * 1024;
+
+/// Most commands whose place in the scrollback is remembered
+const MAX_COMMAND_SPANS: usize = 10_000;
+
+/// Command boundary reported by the shell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandMark {
+    PromptStart,
+    CommandStart,
+    CommandFinished { exit_code: Option<i32> },
+}
+
+impl CommandMark {
+    /// Parse the payload of an OSC sequence, e.g. `133;D;0`
+    pub fn parse(osc: &[u8]) -> Option<Self> {
+        let mut parts = osc.split(|&b| b == b';');
+        if parts.next()? != b"133" {
+            return None;
+        }
+        match parts.next()? {
+            b"A" => Some(CommandMark::PromptStart),
+            b"C" => Some(CommandMark::CommandStart),
+            b"D" => Some(CommandMark::CommandFinished {
+                exit_code: parts
+                    .next()
+                    .and_then(|code| std::str::from_utf8(code).ok())
+                    .and_then(|code| code.parse().ok()),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Output and exit code of one command, returned by `pty_capture_next`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PtyCommandCapture {
+    /// ANSI-stripped output of the command
+    pub output: String,
+    pub exit_code: Option<i32>,
+}
+
+/// A pending `pty_capture_next` call waiting for the next command to finish
+#[derive(Debug)]
+pub struct CommandCapture {
+    started: bool,
+    output: Vec<u8>,
+    tx: oneshot::Sender<PtyCommandCapture>,
+}
+
+impl CommandCapture {
+    pub fn new(tx: oneshot::Sender<PtyCommandCapture>) -> Self {
+        Self {
+            started: false,
+            output: Vec::new(),
+            tx,
+        }
+    }
+
+    /// Append stripped output; ignored until the command has started
+    pub fn push(&mut self, bytes: &[u8]) {
+        if self.started {
+            let room = MAX_CAPTURE_BYTES.saturating_sub(self.output.len());
+            self.output
+                .extend_from_slice(&bytes[..bytes.len().min(room)]);
+        }
+    }
+
+    /// Apply a command mark. Returns the capture back unless it completed.
+    pub fn mark(mut self, mark: CommandMark) -> Option<Self> {
+        match mark {
+            CommandMark::CommandStart if !self.started => {
+                self.started = true;
+                Some(self)
+            }
+            CommandMark::CommandFinished { exit_code } if self.started => {
+                let _ = self.tx.send(PtyCommandCapture {
+                    output: String::from_utf8_lossy(&self.output).to_string(),
+                    exit_code,
+                });
+                None
+            }
+            // A finish without a start is an empty prompt; the next command counts
+            _ => Some(self),
+        }
+    }
+}
+
+/// Where a command's output lies in the scrollback, as absolute offsets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandSpan {
+    pub start: u64,
+    /// `None` while the command is running
+    pub end: Option<u64>,
+}
+
+/// Scrollback spans of the most recent commands, numbered in the order they
+/// ran from 0 for the first command of the session
+#[derive(Debug, Default)]
+pub struct CommandSpans {
+    spans: VecDeque<CommandSpan>,
+    /// Number of the oldest remembered command
+    first: u64,
+}
+
+impl CommandSpans {
+    /// Apply a command mark found at `offset` in the scrollback
+    pub fn mark(&mut self, mark: CommandMark, offset: u64) {
+        // Any mark ends a running command, e.g. a new prompt without a finish
+        // mark after Ctrl-C in some shells
+        if let Some(span) = self.spans.back_mut().filter(|span| span.end.is_none()) {
+            span.end = Some(offset);
+        }
+        if mark == CommandMark::CommandStart {
+            if self.spans.len() == MAX_COMMAND_SPANS {
+                self.spans.pop_front();
+                self.first += 1;
+            }
+            self.spans.push_back(CommandSpan {
+                start: offset,
+                end: None,
+            });
+        }
+    }
+
+    /// Number of commands run so far
+    pub fn count(&self) -> u64 {
+        self.first + self.spans.len() as u64
+    }
+
+    /// Span of a command, `None` if it was forgotten or hasn't run
+    pub fn get(&self, index: u64) -> Option<CommandSpan> {
+        let index = index.checked_sub(self.first)?;
+        self.spans.get(usize::try_from(index).ok()?).copied()
+    }
+}
+
+const BASH_INIT: &str = r#"# TalkCody shell integration, loaded with --init-file.
+# Read the login files bash would normally read, since -l is not passed.
+if [ -r /etc/profile ]; then . /etc/profile; fi
+for __talkcody_rc in ~/.bash_profile ~/.bash_login ~/.profile; do
+    if [ -r "$__talkcody_rc" ]; then . "$__talkcody_rc"; break; fi
+done
+unset __talkcody_rc
+
+__talkcody_precmd() {
+    local ret=$?
+    printf '\e]133;D;%s\a\e]133;A\a' "$ret"
+    return $ret
+}
+PS0="${PS0}"$'\e]133;C\a'
+PROMPT_COMMAND="__talkcody_precmd${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+"#;
+
+const ZSH_HOOKS: &str = r#"autoload -Uz add-zsh-hook
+__talkcody_precmd() {
+    local ret=$?
+    print -n "\e]133;D;$ret\a\e]133;A\a"
+}
+__talkcody_preexec() {
+    print -n "\e]133;C\a"
+}
+add-zsh-hook precmd __talkcody_precmd
+add-zsh-hook preexec __talkcody_preexec
+"#;
+
+/// Startup file that sources the user's own copy with ZDOTDIR pointing at
+/// their directory, then switches back so zsh keeps reading ours
+fn zsh_startup_file(name: &str, extra: &str) -> String {
+    format!(
+        r#"# TalkCody shell integration
+__talkcody_zdotdir=$ZDOTDIR
+ZDOTDIR=$TALKCODY_USER_ZDOTDIR
+[[ -r "$ZDOTDIR/{name}" ]] && . "$ZDOTDIR/{name}"
+TALKCODY_USER_ZDOTDIR=$ZDOTDIR
+ZDOTDIR=$__talkcody_zdotdir
+{extra}"#
+    )
+}
+
+fn integration_dir() -> PathBuf {
+    std::env::temp_dir().join("talkcody-shell-integration")
+}
+
+fn write_script(path: &Path, content: &str) -> Result<(), String> {
+    std::fs::write(path, content)
+        .map_err(|e| format!("Failed to write shell integration script: {}", e))
+}
+
+fn configure_bash(cmd: &mut CommandBuilder) -> Result<(), String> {
+    let dir = integration_dir();
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create shell integration directory: {}", e))?;
+    let init_file = dir.join("bash-init.sh");
+    write_script(&init_file, BASH_INIT)?;
+
+    cmd.arg("--init-file");
+    cmd.arg(init_file);
+    Ok(())
+}
+
+fn configure_zsh(cmd: &mut CommandBuilder) -> Result<(), String> {
+    let dir = integration_dir().join("zsh");
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create shell integration directory: {}", e))?;
+    write_script(&dir.join(".zshenv"), &zsh_startup_file(".zshenv", ""))?;
+    write_script(&dir.join(".zprofile"), &zsh_startup_file(".zprofile", ""))?;
+    write_script(&dir.join(".zshrc"), &zsh_startup_file(".zshrc", ZSH_HOOKS))?;
+    // .zlogin is read last; hand ZDOTDIR back to the user afterwards
+    write_script(
+        &dir.join(".zlogin"),
+        &zsh_startup_file(".zlogin", "ZDOTDIR=$TALKCODY_USER_ZDOTDIR\n"),
+    )?;
+
+    let user_zdotdir = std::env::var("ZDOTDIR")
+        .ok()
+        .or_else(|| dirs::home_dir().map(|home| home.to_string_lossy().to_string()))
+        .unwrap_or_default();
+    cmd.env("TALKCODY_USER_ZDOTDIR", user_zdotdir);
+    cmd.env("ZDOTDIR", dir);
+    cmd.args(["-o", "no_prompt_sp", "-l"]);
+    Ok(())
+}
+
+/// Add shell integration to the command for a supported shell (bash and zsh).
+/// Returns false when the shell isn't supported or setup failed; the caller
+/// then starts the shell normally.
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+pub fn configure(cmd: &mut CommandBuilder, shell: &str) -> bool {
+    let name = Path::new(shell)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let result = if name.contains("zsh") {
+        configure_zsh(cmd)
+    } else if name.contains("bash") {
+        configure_bash(cmd)
+    } else {
+        warn!("Shell integration is not supported for {}", shell);
+        return false;
+    };
+
+    match result {
+        Ok(()) => {
+            info!("Enabled shell integration for {}", shell);
+            true
+        }
+        Err(e) => {
+            warn!("Failed to set up shell integration for {}: {}", shell, e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command_marks() {
+        assert_eq!(CommandMark::parse(b"133;A"), Some(CommandMark::PromptStart));
+        assert_eq!(
+            CommandMark::parse(b"133;D;127"),
+            Some(CommandMark::CommandFinished {
+                exit_code: Some(127)
+            })
+        );
+        assert_eq!(
+            CommandMark::parse(b"133;D"),
+            Some(CommandMark::CommandFinished { exit_code: None })
+        );
+        assert_eq!(CommandMark::parse(b"0;title"), None);
+    }
+
+    #[test]
+    fn test_capture_ignores_finish_without_start() {
+        let (tx, mut rx) = oneshot::channel();
+        let capture = CommandCapture::new(tx);
+        let mut capture = capture
+            .mark(CommandMark::CommandFinished { exit_code: Some(0) })
+            .unwrap();
+        capture.push(b"prompt noise");
+        let mut capture = capture.mark(CommandMark::CommandStart).unwrap();
+        capture.push(b"hello\n");
+        assert!(capture
+            .mark(CommandMark::CommandFinished { exit_code: Some(2) })
+            .is_none());
+
+        let result = rx.try_recv().unwrap();
+        assert_eq!(result.output, "hello\n");
+        assert_eq!(result.exit_code, Some(2));
+    }
+
+    #[test]
+    fn test_command_spans_are_numbered_in_order() {
+        let mut spans = CommandSpans::default();
+        spans.mark(CommandMark::CommandFinished { exit_code: Some(0) }, 5);
+        assert_eq!(spans.count(), 0);
+
+        spans.mark(CommandMark::CommandStart, 10);
+        assert_eq!(
+            spans.get(0),
+            Some(CommandSpan {
+                start: 10,
+                end: None
+            })
+        );
+        spans.mark(CommandMark::CommandFinished { exit_code: Some(0) }, 20);
+        spans.mark(CommandMark::PromptStart, 20);
+        // Interrupted: the next prompt ends it
+        spans.mark(CommandMark::CommandStart, 30);
+        spans.mark(CommandMark::PromptStart, 40);
+
+        assert_eq!(spans.count(), 2);
+        assert_eq!(spans.get(0).unwrap().end, Some(20));
+        assert_eq!(
+            spans.get(1),
+            Some(CommandSpan {
+                start: 30,
+                end: Some(40)
+            })
+        );
+        assert_eq!(spans.get(2), None);
+    }
+}
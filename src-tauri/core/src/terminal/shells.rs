@@ -0,0 +1,266 @@
+//! Shells available for `pty_spawn`, with their versions for compatibility
+//! checks such as "shell integration needs bash 4 or later".
+//!
+//! Detection and version probes launch processes, so both are cached: the
+//! list of shells once, and each shell's version the first time it is listed.
+//! Only shells known to report a version are probed; others, and shells whose
+//! output has no recognizable version number, are listed without one.
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Mutex;
+
+/// A shell found on this machine
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShellInfo {
+    /// Path or command to pass to `pty_spawn` as `preferred_shell`
+    pub path: String,
+    /// File name, e.g. `zsh`
+    pub name: String,
+    /// Best-effort version number, e.g. `5.9`
+    pub version: Option<String>,
+    /// First line of the version output the number was parsed from
+    pub version_output: Option<String>,
+    /// Whether it is the shell `pty_spawn` starts by default
+    pub default: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct ShellVersion {
+    version: Option<String>,
+    output: Option<String>,
+}
+
+lazy_static::lazy_static! {
+    static ref DETECTED_SHELLS: Mutex<Option<Vec<String>>> = Mutex::new(None);
+    /// By path, including shells whose probe found no version
+    static ref SHELL_VERSIONS: Mutex<HashMap<String, ShellVersion>> = Mutex::new(HashMap::new());
+}
+
+/// File name of a shell without extension, e.g. `pwsh` for `C:\...\pwsh.exe`
+fn shell_name(path: &str) -> String {
+    // Split by hand so Windows paths are handled on every platform
+    let file_name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    let name = file_name.to_lowercase();
+    match name.strip_suffix(".exe") {
+        Some(stem) => stem.to_string(),
+        None => name,
+    }
+}
+
+/// Arguments that make a shell print its version, for shells known to
+/// support them
+fn version_args(name: &str) -> Option<&'static [&'static str]> {
+    let args: &'static [&'static str] = match name {
+        "bash" | "rbash" | "zsh" | "fish" | "ksh" | "mksh" | "tcsh" | "nu" | "elvish" | "xonsh"
+        | "pwsh" => &["--version"],
+        "powershell" => &[
+            "-NoLogo",
+            "-NoProfile",
+            "-Command",
+            "$PSVersionTable.PSVersion.ToString()",
+        ],
+        "cmd" => &["/c", "ver"],
+        _ => return None,
+    };
+    Some(args)
+}
+
+/// First dotted version number in `output`, e.g. `5.2.15` in
+/// `GNU bash, version 5.2.15(1)-release (x86_64-pc-linux-gnu)`
+fn parse_version(output: &str) -> Option<String> {
+    output
+        .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .map(|token| token.trim_matches('.'))
+        .find(|token| {
+            let mut parts = token.split('.');
+            parts.clone().count() >= 2 && parts.all(|part| !part.is_empty())
+        })
+        .map(str::to_string)
+}
+
+fn probe_version(path: &str) -> ShellVersion {
+    let Some(args) = version_args(&shell_name(path)) else {
+        return ShellVersion::default();
+    };
+    let output = match crate::shell_utils::new_command(path)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            warn!("{} {:?} exited with {}", path, args, output.status);
+            return ShellVersion::default();
+        }
+        Err(e) => {
+            warn!("Failed to run {} {:?}: {}", path, args, e);
+            return ShellVersion::default();
+        }
+    };
+    let text = String::from_utf8_lossy(&output.stdout);
+    let first_line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string);
+    ShellVersion {
+        version: first_line.as_deref().and_then(parse_version),
+        output: first_line,
+    }
+}
+
+/// Version of a shell, probed on first use
+fn shell_version(path: &str) -> ShellVersion {
+    if let Some(version) = SHELL_VERSIONS.lock().unwrap().get(path) {
+        return version.clone();
+    }
+    // Probe outside the lock so listing doesn't serialize behind a slow shell
+    let version = probe_version(path);
+    info!("Shell {} has version {:?}", path, version.version);
+    SHELL_VERSIONS
+        .lock()
+        .unwrap()
+        .insert(path.to_string(), version.clone());
+    version
+}
+
+/// Login shells listed in an `/etc/shells` file
+#[cfg(not(target_os = "windows"))]
+fn parse_etc_shells(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with('/'))
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(not(target_os = "windows"))]
+fn detect_shells() -> Vec<String> {
+    let mut candidates = std::env::var("SHELL").into_iter().collect::<Vec<_>>();
+    if let Ok(contents) = std::fs::read_to_string("/etc/shells") {
+        candidates.extend(parse_etc_shells(&contents));
+    }
+
+    // Distributions list the same shell under several paths, e.g. /bin/bash
+    // and /usr/bin/bash
+    let mut seen = std::collections::HashSet::new();
+    candidates
+        .into_iter()
+        .filter(|path| Path::new(path).is_file())
+        .filter(|path| seen.insert(std::fs::canonicalize(path).unwrap_or_else(|_| path.into())))
+        .collect()
+}
+
+#[cfg(target_os = "windows")]
+fn detect_shells() -> Vec<String> {
+    super::WINDOWS_SHELLS
+        .iter()
+        .filter(|(cmd, version_args, _)| super::check_shell_available(cmd, version_args))
+        .map(|(cmd, _, _)| cmd.to_string())
+        .collect()
+}
+
+/// Paths of the shells found, detected on first use and cached. On Windows
+/// these are the available [`super::WINDOWS_SHELLS`], in order of preference.
+pub(super) fn detected_shells() -> Vec<String> {
+    DETECTED_SHELLS
+        .lock()
+        .unwrap()
+        .get_or_insert_with(detect_shells)
+        .clone()
+}
+
+pub(super) fn list_shells(refresh: bool) -> Vec<ShellInfo> {
+    if refresh {
+        SHELL_VERSIONS.lock().unwrap().clear();
+        *DETECTED_SHELLS.lock().unwrap() = Some(detect_shells());
+    }
+    let paths = detected_shells();
+
+    let default_shell = super::get_default_shell(None);
+    paths
+        .into_iter()
+        .map(|path| {
+            let version = shell_version(&path);
+            ShellInfo {
+                name: shell_name(&path),
+                default: path == default_shell,
+                path,
+                version: version.version,
+                version_output: version.output,
+            }
+        })
+        .collect()
+}
+
+/// Shells that can be started, with their versions. Both are detected once and
+/// cached; `refresh` detects them again, e.g. after installing a shell.
+#[tauri::command]
+pub async fn pty_list_shells(refresh: Option<bool>) -> Result<Vec<ShellInfo>, String> {
+    let refresh = refresh.unwrap_or(false);
+    tauri::async_runtime::spawn_blocking(move || list_shells(refresh))
+        .await
+        .map_err(|e| format!("Failed to list shells: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            parse_version("GNU bash, version 5.2.15(1)-release (x86_64-pc-linux-gnu)").as_deref(),
+            Some("5.2.15")
+        );
+        assert_eq!(
+            parse_version("zsh 5.9 (x86_64-apple-darwin23.0)").as_deref(),
+            Some("5.9")
+        );
+        assert_eq!(
+            parse_version("fish, version 3.7.1").as_deref(),
+            Some("3.7.1")
+        );
+        assert_eq!(
+            parse_version("Microsoft Windows [Version 10.0.22631.3007]").as_deref(),
+            Some("10.0.22631.3007")
+        );
+        assert_eq!(parse_version("version 5."), None);
+        assert_eq!(parse_version("no version here"), None);
+    }
+
+    #[test]
+    fn test_only_known_shells_are_probed() {
+        assert_eq!(shell_name("/usr/local/bin/zsh"), "zsh");
+        assert_eq!(
+            shell_name(r"C:\Program Files\PowerShell\7\pwsh.exe"),
+            "pwsh"
+        );
+        assert!(version_args("bash").is_some());
+        assert!(version_args("dash").is_none());
+        assert_eq!(probe_version("/usr/bin/tmux"), ShellVersion::default());
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_parse_etc_shells() {
+        let contents = "# /etc/shells: valid login shells\n/bin/sh\n\n  /bin/bash\nnot-a-path\n";
+        assert_eq!(parse_etc_shells(contents), vec!["/bin/sh", "/bin/bash"]);
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_bash_version_is_probed() {
+        if !Path::new("/bin/bash").is_file() {
+            return;
+        }
+        let version = probe_version("/bin/bash");
+        assert!(version.output.unwrap().contains("bash"));
+        assert!(version.version.is_some());
+    }
+}
@@ -0,0 +1,57 @@
+//! Answers to window size queries (xterm window operations), for sessions
+//! started with `auto_respond_size`.
+//!
+//! Handled queries:
+//! - `CSI 18 t`, text area size in characters, answered `CSI 8 ; rows ; cols t`
+//! - `CSI 14 t`, text area size in pixels, answered `CSI 4 ; height ; width t`
+//!   only when the pixel size is known
+//!
+//! Other `CSI ... t` window operations are left to the frontend.
+
+/// Size of the session as last applied to the PTY
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowSize {
+    pub cols: u16,
+    pub rows: u16,
+    /// 0 when unknown
+    pub pixel_width: u16,
+    pub pixel_height: u16,
+}
+
+impl WindowSize {
+    /// Reply to the window operation `CSI params t`, if it is a handled query
+    pub fn reply(&self, params: &[u16]) -> Option<String> {
+        match params {
+            [18] => Some(format!("\x1b[8;{};{}t", self.rows, self.cols)),
+            [14] if self.pixel_width > 0 && self.pixel_height > 0 => Some(format!(
+                "\x1b[4;{};{}t",
+                self.pixel_height, self.pixel_width
+            )),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size_query_replies() {
+        let mut size = WindowSize {
+            cols: 120,
+            rows: 40,
+            pixel_width: 0,
+            pixel_height: 0,
+        };
+        assert_eq!(size.reply(&[18]).as_deref(), Some("\x1b[8;40;120t"));
+        // Pixel size unknown
+        assert_eq!(size.reply(&[14]), None);
+        // Title stack operations aren't queries
+        assert_eq!(size.reply(&[22, 0]), None);
+
+        size.pixel_width = 960;
+        size.pixel_height = 640;
+        assert_eq!(size.reply(&[14]).as_deref(), Some("\x1b[4;640;960t"));
+    }
+}
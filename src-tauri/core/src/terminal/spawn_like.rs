@@ -0,0 +1,264 @@
+//! Spawning a session like an existing one, with the environment its shell has
+//! now, e.g. to open a new terminal after exporting variables in another.
+//!
+//! Unlike the spawn options, which only record what a shell was started with,
+//! the variables a user exported are known only to the shell itself. The source
+//! session is therefore asked to write `env` to a file in a temporary directory
+//! only the user can access, which works only while its shell waits at a
+//! prompt. This is best-effort (Unix only):
+//! - the command is typed into the source session and shows up there; a
+//!   leading space keeps it out of the history of bash and zsh when they are
+//!   set to ignore such lines
+//! - input half typed at the prompt is sent with it and breaks the capture
+//! - shell variables that aren't exported, aliases and functions aren't part of
+//!   `env` and aren't carried over
+//! - a value with line breaks is cut at a line that looks like `NAME=value`
+
+use super::{at_prompt, pty_spawn, pty_write, PtySpawnOptions, PtySpawnResult, PTY_SESSIONS};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tauri::AppHandle;
+
+/// Default time the source shell has to write its environment
+const CAPTURE_TIMEOUT_MS: u64 = 3000;
+const CAPTURE_POLL_MS: u64 = 25;
+
+/// Variables describing the source shell itself rather than what it passes on
+const SKIPPED_VARS: &[&str] = &["_", "PWD", "OLDPWD", "SHLVL"];
+
+/// Changes to the source session's settings for `pty_spawn_like`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PtySpawnLikeOverrides {
+    /// Working directory instead of the source's current one
+    pub cwd: Option<String>,
+    /// Shell instead of the source's
+    pub shell: Option<String>,
+    pub cols: Option<u16>,
+    pub rows: Option<u16>,
+    /// Variables set on top of the captured environment
+    pub env: HashMap<String, String>,
+    /// Options instead of the source's current settings. Their `env` is applied
+    /// on top of the captured environment, below the `env` above.
+    pub options: Option<PtySpawnOptions>,
+    /// How long the source shell has to write its environment (defaults to 3s)
+    pub capture_timeout_ms: Option<u64>,
+}
+
+/// Whether a name can start a `NAME=value` line of `env` output
+fn is_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parse the output of `env`. A line that doesn't start a variable continues
+/// the value of the previous one.
+fn parse_env(output: &str) -> HashMap<String, String> {
+    let mut env = HashMap::new();
+    let mut current: Option<(String, String)> = None;
+    for line in output.lines() {
+        match line.split_once('=') {
+            Some((name, value)) if is_var_name(name) => {
+                env.extend(current.take());
+                current = Some((name.to_string(), value.to_string()));
+            }
+            _ => {
+                if let Some((_, value)) = current.as_mut() {
+                    value.push('\n');
+                    value.push_str(line);
+                }
+            }
+        }
+    }
+    env.extend(current);
+    env.retain(|name, _| !SKIPPED_VARS.contains(&name.as_str()) && !name.starts_with("TALKCODY_"));
+    env
+}
+
+/// Command that makes a shell write its environment to `path`. It's written
+/// next to it first so that the file is complete once it appears.
+fn capture_command(path: &Path) -> Result<String, String> {
+    let path = path.to_string_lossy();
+    if path.contains(['\'', '\\', '\n']) {
+        return Err(format!("Can't quote temporary file path {}", path));
+    }
+    Ok(format!(" env > '{0}.part' && mv '{0}.part' '{0}'\r", path))
+}
+
+/// Create a directory only the current user can access, since the captured
+/// environment often holds secrets such as API tokens
+fn private_dir() -> Result<PathBuf, String> {
+    let dir = std::env::temp_dir().join(format!("talkcody-env-{}", uuid::Uuid::new_v4()));
+    let mut builder = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder
+        .create(&dir)
+        .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    Ok(dir)
+}
+
+/// Ask the shell of a session for its environment
+async fn capture_env(
+    app: &AppHandle,
+    pty_id: &str,
+    timeout: Duration,
+) -> Result<HashMap<String, String>, String> {
+    if !at_prompt(pty_id)? {
+        return Err(format!(
+            "PTY {} is running a command; its environment can only be captured at a prompt",
+            pty_id
+        ));
+    }
+
+    let dir = private_dir()?;
+    let path = dir.join("env");
+    if let Err(e) = capture_command(&path)
+        .and_then(|command| pty_write(app.clone(), pty_id.to_string(), command))
+    {
+        let _ = std::fs::remove_dir_all(&dir);
+        return Err(e);
+    }
+
+    let deadline = Instant::now() + timeout;
+    let result = loop {
+        if let Ok(output) = std::fs::read_to_string(&path) {
+            break Ok(parse_env(&output));
+        }
+        if Instant::now() >= deadline {
+            warn!(
+                "PTY {} did not write its environment within {}ms",
+                pty_id,
+                timeout.as_millis()
+            );
+            break Err(format!(
+                "Timed out after {}ms waiting for PTY {} to write its environment",
+                timeout.as_millis(),
+                pty_id
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(CAPTURE_POLL_MS)).await;
+    };
+    // Once the directory is gone, a shell that answers late can't write there
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+/// Spawn a new session with the environment, working directory and shell the
+/// source session has now, and its settings. See the module docs for the
+/// limits of the environment capture, which needs the source shell at a prompt.
+#[tauri::command]
+pub async fn pty_spawn_like(
+    app: AppHandle,
+    pty_id: String,
+    overrides: Option<PtySpawnLikeOverrides>,
+) -> Result<PtySpawnResult, String> {
+    let overrides = overrides.unwrap_or_default();
+    let (shell, cwd, size, options) = {
+        let sessions = PTY_SESSIONS.lock().unwrap();
+        let session = sessions
+            .get(&pty_id)
+            .ok_or_else(|| format!("PTY session {} not found", pty_id))?;
+        (
+            session.shell.clone(),
+            session.current_cwd(),
+            session.size,
+            session.respawn_options(),
+        )
+    };
+
+    let timeout = Duration::from_millis(overrides.capture_timeout_ms.unwrap_or(CAPTURE_TIMEOUT_MS));
+    let mut env = capture_env(&app, &pty_id, timeout).await?;
+    info!(
+        "Captured {} environment variables from PTY {}",
+        env.len(),
+        pty_id
+    );
+
+    let mut options = overrides.options.unwrap_or(options);
+    env.extend(options.env);
+    env.extend(overrides.env);
+    options.env = env;
+
+    // The shell may have reported a directory that has since been removed
+    let cwd = overrides
+        .cwd
+        .or_else(|| cwd.filter(|cwd| Path::new(cwd).is_dir()));
+    pty_spawn(
+        app,
+        cwd,
+        overrides.cols.or(Some(size.cols)),
+        overrides.rows.or(Some(size.rows)),
+        overrides.shell.or(Some(shell)),
+        Some(options),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_env() {
+        let env = parse_env(
+            "PATH=/usr/bin:/bin\nGREETING=hello\nworld\nEMPTY=\nSHLVL=2\n_=/usr/bin/env\n\
+             TALKCODY_USER_ZDOTDIR=/home/me\nURL=https://example.com/?a=b\n",
+        );
+        assert_eq!(
+            env,
+            HashMap::from([
+                ("PATH".to_string(), "/usr/bin:/bin".to_string()),
+                ("GREETING".to_string(), "hello\nworld".to_string()),
+                ("EMPTY".to_string(), String::new()),
+                ("URL".to_string(), "https://example.com/?a=b".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_var_names() {
+        assert!(is_var_name("_FOO1"));
+        assert!(!is_var_name("1FOO"));
+        assert!(!is_var_name("FOO BAR"));
+        assert!(!is_var_name(""));
+    }
+
+    #[test]
+    fn test_capture_command() {
+        assert_eq!(
+            capture_command(Path::new("/tmp/talkcody-env-1")).unwrap(),
+            " env > '/tmp/talkcody-env-1.part' && mv '/tmp/talkcody-env-1.part' '/tmp/talkcody-env-1'\r"
+        );
+        assert!(capture_command(Path::new("/tmp/it's")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_capture_command_writes_env() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = private_dir().unwrap();
+        let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        let path = dir.join("env");
+        let command = capture_command(&path).unwrap();
+        let status = std::process::Command::new("/bin/sh")
+            .args(["-c", command.trim_end_matches('\r')])
+            .env("TALKCODY_SPAWN_LIKE_TEST", "1")
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let env = parse_env(&std::fs::read_to_string(&path).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(env.contains_key("PATH"));
+        assert!(!env.contains_key("TALKCODY_SPAWN_LIKE_TEST"));
+    }
+}
@@ -0,0 +1,137 @@
+//! A program every session's shell is started through, e.g. `nice`, `taskset`
+//! or a sandbox such as `firejail`, so a policy applies to all spawns without
+//! changing each caller.
+//!
+//! With a wrapper set, `shell -l` is started as `wrapper [args] -- shell -l`.
+//! The `--` ends the wrapper's own options, which most wrappers expect; it can
+//! be left out for those that don't, like `nice`. The session still records the
+//! shell, not the wrapper, as what it runs.
+
+use super::validate::find_program;
+use log::info;
+use portable_pty::CommandBuilder;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::sync::RwLock;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpawnWrapper {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Whether `--` goes between the wrapper's arguments and the shell
+    pub separator: bool,
+}
+
+lazy_static::lazy_static! {
+    static ref SPAWN_WRAPPER: RwLock<Option<SpawnWrapper>> = RwLock::new(None);
+}
+
+impl SpawnWrapper {
+    fn wrap_argv(&self, argv: &[OsString]) -> Vec<OsString> {
+        let mut wrapped: Vec<OsString> = std::iter::once(&self.program)
+            .chain(&self.args)
+            .map(OsString::from)
+            .collect();
+        if self.separator {
+            wrapped.push("--".into());
+        }
+        wrapped.extend(argv.iter().cloned());
+        wrapped
+    }
+
+    /// Start the command through the wrapper, keeping its environment and
+    /// working directory
+    pub fn wrap(&self, cmd: &mut CommandBuilder) {
+        let argv = cmd.get_argv_mut();
+        *argv = self.wrap_argv(argv);
+    }
+}
+
+/// The wrapper for the next spawn, if one is set. Fails if its program can't be
+/// found, rather than starting the shell without it.
+pub(super) fn current() -> Result<Option<SpawnWrapper>, String> {
+    let Some(wrapper) = SPAWN_WRAPPER.read().unwrap().clone() else {
+        return Ok(None);
+    };
+    if find_program(&wrapper.program).is_none() {
+        return Err(format!("Spawn wrapper {} not found", wrapper.program));
+    }
+    Ok(Some(wrapper))
+}
+
+/// Start every new session's shell through `program` with `args`, followed by
+/// `--` unless `separator` is false. Takes effect for sessions spawned after
+/// the call, including `pty_change_shell`. An empty `program` removes the
+/// wrapper. The program is looked up at each spawn, which fails if it's missing.
+#[tauri::command]
+pub fn pty_set_spawn_wrapper(
+    program: String,
+    args: Option<Vec<String>>,
+    separator: Option<bool>,
+) -> Result<(), String> {
+    let program = program.trim().to_string();
+    let wrapper = if program.is_empty() {
+        info!("Removed spawn wrapper");
+        None
+    } else {
+        let wrapper = SpawnWrapper {
+            program,
+            args: args.unwrap_or_default(),
+            separator: separator.unwrap_or(true),
+        };
+        info!(
+            "Starting shells through {} {:?}",
+            wrapper.program, wrapper.args
+        );
+        Some(wrapper)
+    };
+    *SPAWN_WRAPPER.write().unwrap() = wrapper;
+    Ok(())
+}
+
+/// The wrapper set with `pty_set_spawn_wrapper`, if any
+#[tauri::command]
+pub fn pty_get_spawn_wrapper() -> Option<SpawnWrapper> {
+    SPAWN_WRAPPER.read().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wrapper(separator: bool) -> SpawnWrapper {
+        SpawnWrapper {
+            program: "taskset".to_string(),
+            args: vec!["-c".to_string(), "0-3".to_string()],
+            separator,
+        }
+    }
+
+    #[test]
+    fn test_wrapper_goes_before_the_shell() {
+        let shell = ["/bin/zsh".into(), "-l".into()];
+        assert_eq!(
+            wrapper(true).wrap_argv(&shell),
+            ["taskset", "-c", "0-3", "--", "/bin/zsh", "-l"]
+        );
+        assert_eq!(
+            wrapper(false).wrap_argv(&shell),
+            ["taskset", "-c", "0-3", "/bin/zsh", "-l"]
+        );
+    }
+
+    #[test]
+    fn test_wrap_keeps_env_and_cwd() {
+        let mut cmd = CommandBuilder::new("/bin/sh");
+        cmd.arg("-l");
+        cmd.env("TERM", "xterm-256color");
+        cmd.cwd("/tmp");
+        wrapper(true).wrap(&mut cmd);
+        assert_eq!(
+            cmd.get_argv(),
+            &["taskset", "-c", "0-3", "--", "/bin/sh", "-l"]
+        );
+        assert_eq!(cmd.get_env("TERM").unwrap(), "xterm-256color");
+        assert_eq!(cmd.get_cwd().unwrap(), "/tmp");
+    }
+}
@@ -0,0 +1,73 @@
+//! Split panes: a new shell next to an existing session, started where the
+//! user is working in it.
+//!
+//! A split is spawned like the source session was, with the source's shell,
+//! settings, `env` and group, in the directory its shell last reported. The
+//! global environment overlay applies as to any spawn. The source's exported
+//! variables aren't captured; use `pty_spawn_like` for that. Unlike an
+//! unrelated session, a split remembers its source (`split_of` in
+//! `pty_get_info`) and is announced with `pty-split`.
+
+use super::events::{self, PtyEvent};
+use super::{pty_spawn, PtySpawnResult, PTY_SESSIONS};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tauri::AppHandle;
+
+/// Payload of the `pty-split` event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PtySplit {
+    pub parent_id: String,
+    pub child_id: String,
+}
+
+/// Spawn a session split from `pty_id`, see the module docs. The split has the
+/// source's size unless `cols` or `rows` are given, e.g. half of it.
+#[tauri::command]
+pub async fn pty_split(
+    app: AppHandle,
+    pty_id: String,
+    cols: Option<u16>,
+    rows: Option<u16>,
+) -> Result<PtySpawnResult, String> {
+    let (shell, cwd, size, mut options) = {
+        let sessions = PTY_SESSIONS.lock().unwrap();
+        let session = sessions
+            .get(&pty_id)
+            .ok_or_else(|| format!("PTY session {} not found", pty_id))?;
+        (
+            session.shell.clone(),
+            session.current_cwd(),
+            session.size,
+            session.respawn_options(),
+        )
+    };
+    // A pane of its own, not another tab with the source's name
+    options.name = None;
+
+    // The shell may have reported a directory that has since been removed
+    let cwd = cwd.filter(|cwd| Path::new(cwd).is_dir());
+    let result = pty_spawn(
+        app.clone(),
+        cwd,
+        cols.or(Some(size.cols)),
+        rows.or(Some(size.rows)),
+        Some(shell),
+        Some(options),
+    )
+    .await?;
+
+    if let Some(session) = PTY_SESSIONS.lock().unwrap().get_mut(&result.pty_id) {
+        session.split_of = Some(pty_id.clone());
+    }
+    info!("Split PTY {} into {}", pty_id, result.pty_id);
+    events::emit(
+        &app,
+        PtyEvent::Split(PtySplit {
+            parent_id: pty_id,
+            child_id: result.pty_id.clone(),
+        }),
+    );
+    Ok(result)
+}
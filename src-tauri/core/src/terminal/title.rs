@@ -0,0 +1,121 @@
+//! Window title tracking: OSC 0 / OSC 2 set the title, and xterm's title stack
+//! (`CSI 22 ; 0 t` push, `CSI 23 ; 0 t` pop) lets programs restore it on exit.
+
+use serde::{Deserialize, Serialize};
+
+/// xterm keeps at most this many saved titles
+const MAX_TITLE_STACK: usize = 10;
+
+/// Payload of the `pty-title` event. An empty title means the program cleared
+/// it and the frontend should show its default.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PtyTitle {
+    pub pty_id: String,
+    pub title: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TitleOp {
+    Set(String),
+    Push,
+    Pop,
+}
+
+impl TitleOp {
+    /// Parse an OSC payload: `0;title` (icon name and title) or `2;title`
+    pub fn from_osc(osc: &[u8]) -> Option<Self> {
+        let title = osc
+            .strip_prefix(b"0;")
+            .or_else(|| osc.strip_prefix(b"2;"))?;
+        Some(TitleOp::Set(String::from_utf8_lossy(title).to_string()))
+    }
+
+    /// Parse the parameters of `CSI ... t`. Only pushes and pops that include
+    /// the window title (second parameter 0, 2 or omitted) matter here.
+    pub fn from_csi(params: &[u16]) -> Option<Self> {
+        if !matches!(params.get(1).copied().unwrap_or(0), 0 | 2) {
+            return None;
+        }
+        match params.first() {
+            Some(22) => Some(TitleOp::Push),
+            Some(23) => Some(TitleOp::Pop),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TitleState {
+    /// Current title; `None` until a program sets one
+    pub title: Option<String>,
+    stack: Vec<Option<String>>,
+}
+
+impl TitleState {
+    /// Apply a title operation and return the title to emit, if it changed
+    pub fn apply(&mut self, op: TitleOp) -> Option<String> {
+        match op {
+            TitleOp::Set(title) => {
+                self.title = Some(title.clone());
+                Some(title)
+            }
+            TitleOp::Push => {
+                if self.stack.len() == MAX_TITLE_STACK {
+                    self.stack.remove(0);
+                }
+                self.stack.push(self.title.clone());
+                None
+            }
+            // Popping an empty stack is a no-op, as in xterm
+            TitleOp::Pop => {
+                let restored = self.stack.pop()?;
+                let changed = restored != self.title;
+                self.title = restored;
+                changed.then(|| self.title.clone().unwrap_or_default())
+            }
+        }
+    }
+
+    pub fn stack_depth(&self) -> usize {
+        self.stack.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_title_stack_restores_previous_title() {
+        let mut state = TitleState::default();
+        assert_eq!(
+            state
+                .apply(TitleOp::Set("~/project".to_string()))
+                .as_deref(),
+            Some("~/project")
+        );
+        assert_eq!(state.apply(TitleOp::Push), None);
+        state.apply(TitleOp::Set("vim notes.txt".to_string()));
+        assert_eq!(state.apply(TitleOp::Pop).as_deref(), Some("~/project"));
+        assert_eq!(state.title.as_deref(), Some("~/project"));
+
+        // Underflow leaves the title alone
+        assert_eq!(state.apply(TitleOp::Pop), None);
+        assert_eq!(state.title.as_deref(), Some("~/project"));
+    }
+
+    #[test]
+    fn test_parse_title_ops() {
+        assert_eq!(
+            TitleOp::from_osc(b"2;build"),
+            Some(TitleOp::Set("build".to_string()))
+        );
+        assert_eq!(TitleOp::from_osc(b"1;icon"), None);
+        assert_eq!(TitleOp::from_csi(&[22, 0]), Some(TitleOp::Push));
+        assert_eq!(TitleOp::from_csi(&[23]), Some(TitleOp::Pop));
+        // Icon-name only
+        assert_eq!(TitleOp::from_csi(&[22, 1]), None);
+        // Window size report, not a title operation
+        assert_eq!(TitleOp::from_csi(&[18]), None);
+    }
+}
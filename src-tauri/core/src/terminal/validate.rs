@@ -0,0 +1,215 @@
+//! Dry-run check of spawn options, for showing errors in a profile editor
+//! before anything is launched.
+
+use super::coalesce;
+use super::groups;
+use super::scrollback::ScrollbackLimit;
+use super::spawn_wrapper;
+use super::{get_default_shell, PtySpawnOptions};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Find a program such as a shell: a path as given, or a bare name looked up
+/// in `PATH`
+pub(super) fn find_program(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+    let dirs = std::env::var_os("PATH")?;
+    std::env::split_paths(&dirs).find_map(|dir| {
+        let candidate = dir.join(program);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        #[cfg(target_os = "windows")]
+        {
+            let candidate = dir.join(format!("{}.exe", program));
+            if candidate.is_file() {
+                return Some(candidate);
+            }
+        }
+        None
+    })
+}
+
+/// Fails on the first name that can't be set as an environment variable
+pub(super) fn check_env_names(env: &HashMap<String, String>) -> Result<(), String> {
+    match env
+        .keys()
+        .find(|key| key.is_empty() || key.contains(['=', '\0']))
+    {
+        Some(key) => Err(format!("Invalid environment variable name {:?}", key)),
+        None => Ok(()),
+    }
+}
+
+/// Settings that have no effect without another one, or conflict with it
+fn conflicts(options: &PtySpawnOptions) -> Vec<String> {
+    let mut problems = Vec::new();
+    if options.flow_window_bytes.is_some() && !options.flow_control {
+        problems.push("flow_window_bytes has no effect without flow_control".to_string());
+    }
+    if options.ready_timeout_ms.is_some() && !options.wait_for_ready {
+        problems.push("ready_timeout_ms has no effect without wait_for_ready".to_string());
+    }
+    if options.initial_scrollback.is_some() && options.initial_scrollback_file.is_some() {
+        problems
+            .push("initial_scrollback and initial_scrollback_file can't both be set".to_string());
+    }
+    if options.flush_interval_ms.is_some() && options.low_latency {
+        problems.push("flush_interval_ms has no effect with low_latency".to_string());
+    }
+    match (options.max_scrollback_lines, options.scrollback_limit) {
+        (Some(_), Some(ScrollbackLimit::Bytes)) => problems
+            .push("max_scrollback_lines has no effect with scrollback_limit bytes".to_string()),
+        (None, Some(ScrollbackLimit::Lines | ScrollbackLimit::Both)) => {
+            problems.push("scrollback_limit lines or both needs max_scrollback_lines".to_string())
+        }
+        _ => {}
+    }
+    problems
+}
+
+/// Every problem that would make `pty_spawn` fail with these arguments, or make
+/// some of its options have no effect
+fn problems(
+    cwd: Option<&str>,
+    cols: Option<u16>,
+    rows: Option<u16>,
+    preferred_shell: Option<&str>,
+    options: &PtySpawnOptions,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let shell = get_default_shell(preferred_shell);
+    if find_program(&shell).is_none() {
+        problems.push(format!("Shell {} not found", shell));
+    }
+    problems.extend(spawn_wrapper::current().err());
+    if let Some(cwd) = cwd {
+        if !Path::new(cwd).is_dir() {
+            problems.push(format!("Working directory {} is not a directory", cwd));
+        }
+    }
+    if cols == Some(0) || rows == Some(0) {
+        problems.push("Columns and rows must be at least 1".to_string());
+    }
+    if let Some(group) = &options.group {
+        problems.extend(groups::ensure_group_exists(group).err());
+    }
+    if let Some(interval_ms) = options.flush_interval_ms {
+        problems.extend(coalesce::validate_flush_interval(interval_ms).err());
+    }
+    if options.scrollback_bytes == Some(0) {
+        problems.push("scrollback_bytes must be at least 1".to_string());
+    }
+    if options.max_scrollback_lines == Some(0) {
+        problems.push("max_scrollback_lines must be at least 1".to_string());
+    }
+    if let Some(path) = &options.initial_scrollback_file {
+        if !Path::new(path).is_file() {
+            problems.push(format!("Initial scrollback file {} not found", path));
+        }
+    }
+    if let Some(program) = &options.on_exit_command {
+        if find_program(program).is_none() {
+            problems.push(format!("Exit command {} not found", program));
+        }
+    }
+    if let Some(limits) = &options.resource_limits {
+        problems.extend(limits.check().err());
+    }
+    problems.extend(check_env_names(&options.env).err());
+    problems.extend(conflicts(options));
+    problems
+}
+
+/// Check the arguments of `pty_spawn` without opening a PTY or starting the
+/// shell: that the shell and spawn wrapper exist, the working directory is valid, the options
+/// can be applied and don't contradict each other. Fails with every problem
+/// found, separated by "; ".
+#[tauri::command]
+pub fn pty_validate_spawn(
+    cwd: Option<String>,
+    cols: Option<u16>,
+    rows: Option<u16>,
+    preferred_shell: Option<String>,
+    options: Option<PtySpawnOptions>,
+) -> Result<(), String> {
+    let problems = problems(
+        cwd.as_deref(),
+        cols,
+        rows,
+        preferred_shell.as_deref(),
+        &options.unwrap_or_default(),
+    );
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_program() {
+        assert_eq!(find_program("/bin/sh"), Some(PathBuf::from("/bin/sh")));
+        assert!(find_program("sh").is_some());
+        assert_eq!(find_program("/no/such/shell"), None);
+        assert_eq!(find_program("no-such-shell-talkcody"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_problems_are_all_reported() {
+        let options = PtySpawnOptions {
+            flow_window_bytes: Some(1024),
+            flush_interval_ms: Some(5000),
+            env: HashMap::from([("A=B".to_string(), String::new())]),
+            ..Default::default()
+        };
+        let found = problems(
+            Some("/no/such/dir"),
+            Some(80),
+            Some(0),
+            Some("/no/such/shell"),
+            &options,
+        );
+        assert_eq!(found.len(), 6, "{:?}", found);
+        assert!(found[0].starts_with("Shell /no/such/shell"));
+
+        let valid = problems(
+            Some("/"),
+            None,
+            None,
+            Some("/bin/sh"),
+            &PtySpawnOptions::default(),
+        );
+        assert!(valid.is_empty(), "{:?}", valid);
+    }
+
+    #[test]
+    fn test_conflicting_options() {
+        let options = PtySpawnOptions {
+            low_latency: true,
+            flush_interval_ms: Some(10),
+            ready_timeout_ms: Some(1000),
+            max_scrollback_lines: Some(1000),
+            scrollback_limit: Some(ScrollbackLimit::Bytes),
+            ..Default::default()
+        };
+        assert_eq!(conflicts(&options).len(), 3);
+
+        let options = PtySpawnOptions {
+            wait_for_ready: true,
+            ready_timeout_ms: Some(1000),
+            ..Default::default()
+        };
+        assert!(conflicts(&options).is_empty());
+    }
+}
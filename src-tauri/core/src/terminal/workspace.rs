@@ -0,0 +1,226 @@
+//! Save and restore the layout of all live sessions as a shareable file.
+//!
+//! A workspace records how each session was started (shell, cwd, size, name,
+//! group and metadata), not its process state: importing spawns fresh shells.
+
+use super::events::{self, PtyEvent};
+use super::{groups, spawn_session, PtySpawnOptions, PTY_SESSIONS};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use tauri::AppHandle;
+
+/// Current workspace file format version
+const WORKSPACE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PtyWorkspace {
+    pub version: u32,
+    pub sessions: Vec<PtyWorkspaceSession>,
+}
+
+/// One session in a workspace file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PtyWorkspaceSession {
+    pub shell: String,
+    #[serde(default)]
+    pub cwd: Option<String>,
+    pub cols: u16,
+    pub rows: u16,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PtyWorkspaceImportResult {
+    /// Ids of the re-spawned sessions, in file order
+    pub pty_ids: Vec<String>,
+    /// Number of sessions that could not be restored
+    pub skipped: usize,
+}
+
+/// Payload of the `pty-workspace-warning` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PtyWorkspaceWarning {
+    pub path: String,
+    pub name: Option<String>,
+    pub message: String,
+}
+
+/// Snapshot every live session, in spawn order
+fn snapshot_sessions() -> Vec<PtyWorkspaceSession> {
+    let sessions = PTY_SESSIONS.lock().unwrap();
+    let mut entries: Vec<_> = sessions.values().collect();
+    entries.sort_by_key(|session| session.spawn_seq);
+    entries
+        .into_iter()
+        .map(|session| PtyWorkspaceSession {
+            shell: session.shell.clone(),
+            cwd: session.current_cwd(),
+            cols: session.size.cols,
+            rows: session.size.rows,
+            name: session.name.clone(),
+            group: session.group.clone(),
+            metadata: session.metadata.clone(),
+        })
+        .collect()
+}
+
+fn parse_workspace(content: &str) -> Result<PtyWorkspace, String> {
+    let workspace: PtyWorkspace = serde_json::from_str(content)
+        .map_err(|e| format!("Failed to parse workspace file: {}", e))?;
+    if workspace.version > WORKSPACE_VERSION {
+        return Err(format!(
+            "Unsupported workspace version {} (expected {} or lower)",
+            workspace.version, WORKSPACE_VERSION
+        ));
+    }
+    Ok(workspace)
+}
+
+/// Reason a session can't be restored before trying to spawn it
+fn check_restorable(session: &PtyWorkspaceSession) -> Option<String> {
+    match &session.cwd {
+        Some(cwd) if !Path::new(cwd).is_dir() => {
+            Some(format!("Working directory {} no longer exists", cwd))
+        }
+        _ => None,
+    }
+}
+
+fn emit_warning(app: &AppHandle, path: &str, session: &PtyWorkspaceSession, message: String) {
+    warn!("Skipping workspace session from {}: {}", path, message);
+    events::emit(
+        app,
+        PtyEvent::WorkspaceWarning(PtyWorkspaceWarning {
+            path: path.to_string(),
+            name: session.name.clone(),
+            message,
+        }),
+    );
+}
+
+/// Write every live session's layout to `path`. Returns the number of sessions saved.
+#[tauri::command]
+pub fn pty_export_workspace(path: String) -> Result<usize, String> {
+    let workspace = PtyWorkspace {
+        version: WORKSPACE_VERSION,
+        sessions: snapshot_sessions(),
+    };
+    let content = serde_json::to_string_pretty(&workspace)
+        .map_err(|e| format!("Failed to serialize workspace: {}", e))?;
+
+    if let Some(parent) = Path::new(&path).parent() {
+        if !parent.as_os_str().is_empty() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create workspace directory: {}", e))?;
+        }
+    }
+    std::fs::write(&path, content).map_err(|e| format!("Failed to write workspace file: {}", e))?;
+
+    info!(
+        "Exported {} PTY sessions to workspace {}",
+        workspace.sessions.len(),
+        path
+    );
+    Ok(workspace.sessions.len())
+}
+
+/// Re-spawn the sessions saved in a workspace file. Sessions whose working
+/// directory is gone, or that fail to spawn, are skipped with a
+/// `pty-workspace-warning` event.
+#[tauri::command]
+pub async fn pty_import_workspace(
+    app: AppHandle,
+    path: String,
+) -> Result<PtyWorkspaceImportResult, String> {
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read workspace file: {}", e))?;
+    let workspace = parse_workspace(&content)?;
+
+    let mut result = PtyWorkspaceImportResult {
+        pty_ids: Vec::new(),
+        skipped: 0,
+    };
+    let sink = events::sink(&app);
+    for session in workspace.sessions {
+        if let Some(message) = check_restorable(&session) {
+            emit_warning(&app, &path, &session, message);
+            result.skipped += 1;
+            continue;
+        }
+
+        if let Some(group) = &session.group {
+            groups::ensure_group(&app, group);
+        }
+        let options = PtySpawnOptions {
+            group: session.group.clone(),
+            name: session.name.clone(),
+            metadata: session.metadata.clone(),
+            ..Default::default()
+        };
+        match spawn_session(
+            &sink,
+            session.cwd.clone(),
+            Some(session.cols),
+            Some(session.rows),
+            Some(session.shell.clone()).filter(|shell| !shell.is_empty()),
+            options,
+        ) {
+            Ok(pty_id) => result.pty_ids.push(pty_id),
+            Err(e) => {
+                emit_warning(&app, &path, &session, e);
+                result.skipped += 1;
+            }
+        }
+    }
+
+    info!(
+        "Imported workspace {}: {} sessions restored, {} skipped",
+        path,
+        result.pty_ids.len(),
+        result.skipped
+    );
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_workspace_defaults_optional_fields() {
+        let workspace = parse_workspace(
+            r#"{"version":1,"sessions":[{"shell":"/bin/bash","cols":80,"rows":24}]}"#,
+        )
+        .unwrap();
+        let session = &workspace.sessions[0];
+        assert_eq!(session.shell, "/bin/bash");
+        assert!(session.cwd.is_none());
+        assert!(session.metadata.is_empty());
+
+        assert!(parse_workspace(r#"{"version":99,"sessions":[]}"#).is_err());
+    }
+
+    #[test]
+    fn test_missing_cwd_is_not_restorable() {
+        let mut session = PtyWorkspaceSession {
+            shell: "/bin/sh".to_string(),
+            cwd: Some(std::env::temp_dir().to_string_lossy().to_string()),
+            cols: 80,
+            rows: 24,
+            name: None,
+            group: None,
+            metadata: HashMap::new(),
+        };
+        assert!(check_restorable(&session).is_none());
+
+        session.cwd = Some("/definitely/not/a/real/dir".to_string());
+        assert!(check_restorable(&session).is_some());
+    }
+}
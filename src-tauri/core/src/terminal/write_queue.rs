@@ -0,0 +1,389 @@
+//! Background writes for input too large to write in one go, such as a huge
+//! paste, or the part of a write the PTY didn't take because it was full. A
+//! child that reads slowly would otherwise keep `pty_write` blocked in a single
+//! `write_all`, holding the session registry the whole time.
+//!
+//! Queued writes go out in chunks of [`WRITE_CHUNK_BYTES`] on a per-session
+//! thread, in order, locking the writer only for one chunk at a time. With
+//! latency tracking, a queued write counts from when it was requested until its
+//! last chunk is flushed, so the time spent waiting in the queue shows up.
+
+use super::latency::SharedLatency;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Writes larger than this are queued, and written in chunks of this size
+pub const WRITE_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Writer of a session, shared between direct writes and its write queue
+pub type SharedWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// Payload of the `pty-write-progress` event, sent after each chunk of a queued
+/// write and once more if it fails
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PtyWriteProgress {
+    pub pty_id: String,
+    /// Bytes of this write passed to the PTY so far
+    pub written: u64,
+    pub total: u64,
+    /// Set when the write failed; the rest of it is dropped
+    pub error: Option<String>,
+}
+
+/// Queue of writes handled by a background thread, which exits once the queue
+/// is dropped and the remaining writes are done
+#[derive(Debug)]
+pub struct WriteQueue {
+    /// Data to write, and when it was requested if its latency counts
+    jobs: Sender<(Vec<u8>, Option<Instant>)>,
+    /// Bytes queued but not yet written
+    pending: Arc<AtomicU64>,
+}
+
+impl WriteQueue {
+    pub fn new(
+        pty_id: String,
+        writer: SharedWriter,
+        latency: SharedLatency,
+        on_progress: impl Fn(PtyWriteProgress) + Send + 'static,
+    ) -> Self {
+        let (jobs, rx) = mpsc::channel::<(Vec<u8>, Option<Instant>)>();
+        let pending = Arc::new(AtomicU64::new(0));
+        let queued = pending.clone();
+        std::thread::spawn(move || {
+            for (data, started) in rx {
+                let total = data.len() as u64;
+                let mut written = 0;
+                for chunk in data.chunks(WRITE_CHUNK_BYTES) {
+                    let result = {
+                        let mut writer = writer.lock().unwrap();
+                        writer.write_all(chunk).and_then(|()| writer.flush())
+                    };
+                    if let Err(e) = result {
+                        queued.fetch_sub(total - written, Ordering::SeqCst);
+                        on_progress(PtyWriteProgress {
+                            pty_id: pty_id.clone(),
+                            written,
+                            total,
+                            error: Some(format!("Failed to write to PTY: {}", e)),
+                        });
+                        break;
+                    }
+                    written += chunk.len() as u64;
+                    queued.fetch_sub(chunk.len() as u64, Ordering::SeqCst);
+                    if written == total {
+                        if let (Some(started), Some(window)) =
+                            (started, latency.lock().unwrap().as_mut())
+                        {
+                            window.record(started.elapsed());
+                        }
+                    }
+                    on_progress(PtyWriteProgress {
+                        pty_id: pty_id.clone(),
+                        written,
+                        total,
+                        error: None,
+                    });
+                }
+            }
+        });
+        Self { jobs, pending }
+    }
+
+    /// Bytes queued but not yet written
+    pub fn pending(&self) -> u64 {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /// Queue `data` to be written after everything queued before it. Its
+    /// latency is recorded from `started`, if given, once it's all written.
+    pub fn push(&self, data: Vec<u8>, started: Option<Instant>) -> Result<(), String> {
+        let len = data.len() as u64;
+        self.pending.fetch_add(len, Ordering::SeqCst);
+        self.jobs.send((data, started)).map_err(|_| {
+            self.pending.fetch_sub(len, Ordering::SeqCst);
+            "PTY write queue has stopped".to_string()
+        })
+    }
+}
+
+/// Whether `fd` can accept data right now without blocking
+#[cfg(unix)]
+pub fn poll_writable(fd: std::os::unix::io::RawFd) -> bool {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLOUT,
+        revents: 0,
+    };
+    // Zero timeout: only report the current state
+    // SAFETY: `pollfd` is a single valid pollfd; an invalid `fd` is reported
+    // in `revents`, not undefined behavior
+    let ready = unsafe { libc::poll(&mut pollfd, 1, 0) };
+    ready > 0
+        && pollfd.revents & libc::POLLOUT != 0
+        && pollfd.revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) == 0
+}
+
+/// Write as much of `data` as `fd` takes right now and return how much that
+/// was. Writable only means there is some room, not room for all of `data`, so
+/// `fd` is switched to non-blocking for the write. `writer` must write to `fd`
+/// or a duplicate of it.
+///
+/// The flag is shared by every handle on a PTY master, which can't be opened a
+/// second time, so a read by the read loop that starts during the write fails
+/// with `WouldBlock`. The read loop then waits for output with
+/// [`wait_readable`] rather than spinning. Besides that rare retry, the cost is
+/// two `fcntl` calls per direct write.
+#[cfg(unix)]
+pub fn write_nonblocking(
+    fd: std::os::unix::io::RawFd,
+    writer: &mut dyn Write,
+    data: &[u8],
+) -> std::io::Result<usize> {
+    // SAFETY: F_GETFL and F_SETFL take no pointers; an invalid `fd` fails with
+    // EBADF
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    // SAFETY: as above
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut written = 0;
+    let result = loop {
+        if written == data.len() {
+            break writer.flush();
+        }
+        match writer.write(&data[written..]) {
+            Ok(0) => break Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => written += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    // SAFETY: as above
+    unsafe { libc::fcntl(fd, libc::F_SETFL, flags) };
+    result.map(|()| written)
+}
+
+/// A handle of its own on `fd` for the read loop to wait on with
+/// [`wait_readable`], open for as long as the read loop runs
+#[cfg(unix)]
+pub fn read_waker(fd: std::os::unix::io::RawFd) -> Option<std::os::fd::OwnedFd> {
+    // SAFETY: `fd` is the master of a session the caller holds, so it's open
+    // while it's duplicated
+    unsafe { std::os::fd::BorrowedFd::borrow_raw(fd) }
+        .try_clone_to_owned()
+        .ok()
+}
+
+/// Wait up to `timeout_ms` for `fd` to have data to read, after a read failed
+/// with `WouldBlock` because of [`write_nonblocking`]
+#[cfg(unix)]
+pub fn wait_readable(fd: &std::os::fd::OwnedFd, timeout_ms: i32) {
+    use std::os::fd::AsRawFd;
+
+    let mut pollfd = libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: `pollfd` is a single valid pollfd for an open descriptor
+    unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::latency::LatencyWindow;
+    use std::time::Duration;
+
+    /// Accepts a few KiB per call and takes a while to do so, like a child
+    /// that reads slowly
+    struct SlowReader {
+        received: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Write for SlowReader {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            std::thread::sleep(Duration::from_micros(20));
+            let n = buf.len().min(4096);
+            self.received.lock().unwrap().extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_large_paste_into_slow_reader() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let writer: SharedWriter = Arc::new(Mutex::new(Box::new(SlowReader {
+            received: received.clone(),
+        })));
+        let latency: SharedLatency = Arc::new(Mutex::new(Some(LatencyWindow::new())));
+        let (tx, rx) = mpsc::channel();
+        let queue = WriteQueue::new(
+            "paste".to_string(),
+            writer.clone(),
+            latency.clone(),
+            move |progress| {
+                let _ = tx.send(progress);
+            },
+        );
+
+        let paste: Vec<u8> = (0..10 * 1024 * 1024)
+            .map(|i| b'a' + (i % 26) as u8)
+            .collect();
+        let requested = Instant::now();
+        queue.push(paste.clone(), Some(requested)).unwrap();
+        queue.push(b"\r".to_vec(), None).unwrap();
+        assert!(queue.pending() > 0);
+
+        // The writer is only held for a chunk at a time, so others get a turn
+        let started = Instant::now();
+        drop(writer.lock().unwrap());
+        assert!(started.elapsed() < Duration::from_millis(500));
+
+        let mut last_written = 0;
+        loop {
+            let progress = rx.recv_timeout(Duration::from_secs(30)).unwrap();
+            assert_eq!(progress.pty_id, "paste");
+            assert!(progress.error.is_none());
+            if progress.total == 1 {
+                break;
+            }
+            assert_eq!(progress.total, paste.len() as u64);
+            assert!(progress.written > last_written);
+            assert!(progress.written - last_written <= WRITE_CHUNK_BYTES as u64);
+            last_written = progress.written;
+        }
+        assert_eq!(last_written, paste.len() as u64);
+        assert_eq!(queue.pending(), 0);
+
+        // Only the paste counts, for as long as it took to get it all written
+        let stats = latency.lock().unwrap().as_ref().unwrap().stats().unwrap();
+        assert_eq!(stats.total, 1);
+        assert!(stats.max_us <= requested.elapsed().as_micros() as u64);
+        assert!(stats.max_us >= 1000);
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), paste.len() + 1);
+        assert_eq!(&received[..paste.len()], &paste[..]);
+        assert_eq!(received.last(), Some(&b'\r'));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_poll_writable_reports_full_pipe() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let [read_fd, write_fd] = fds;
+        assert!(poll_writable(write_fd));
+
+        // Fill the pipe without blocking
+        unsafe { libc::fcntl(write_fd, libc::F_SETFL, libc::O_NONBLOCK) };
+        let buf = [0u8; 4096];
+        while unsafe { libc::write(write_fd, buf.as_ptr().cast(), buf.len()) } > 0 {}
+        assert!(!poll_writable(write_fd));
+
+        unsafe {
+            libc::close(read_fd);
+            libc::close(write_fd);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_nonblocking_stops_when_full() {
+        use std::os::unix::io::FromRawFd;
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let [read_fd, write_fd] = fds;
+        let mut pipe = unsafe { std::fs::File::from_raw_fd(libc::dup(write_fd)) };
+
+        // Far more than a pipe holds, with nothing reading
+        let data = vec![b'x'; 4 * 1024 * 1024];
+        let written = write_nonblocking(write_fd, &mut pipe, &data).unwrap();
+        assert!(written > 0 && written < data.len());
+        assert_eq!(write_nonblocking(write_fd, &mut pipe, &data).unwrap(), 0);
+        // Left blocking as it was
+        assert_eq!(
+            unsafe { libc::fcntl(write_fd, libc::F_GETFL) } & libc::O_NONBLOCK,
+            0
+        );
+
+        drop(pipe);
+        unsafe {
+            libc::close(read_fd);
+            libc::close(write_fd);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_wait_readable_returns_once_there_is_data() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let [read_fd, write_fd] = fds;
+        let waker = read_waker(read_fd).unwrap();
+
+        let started = Instant::now();
+        wait_readable(&waker, 50);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        assert_eq!(unsafe { libc::write(write_fd, b"x".as_ptr().cast(), 1) }, 1);
+        let started = Instant::now();
+        wait_readable(&waker, 5000);
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        unsafe {
+            libc::close(read_fd);
+            libc::close(write_fd);
+        }
+    }
+
+    /// Fails every write, like a PTY whose child has exited
+    struct ClosedReader;
+
+    impl Write for ClosedReader {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_failed_write_reports_error_and_drops_rest() {
+        let writer: SharedWriter = Arc::new(Mutex::new(Box::new(ClosedReader)));
+        let (tx, rx) = mpsc::channel();
+        let latency: SharedLatency = Arc::new(Mutex::new(Some(LatencyWindow::new())));
+        let queue = WriteQueue::new(
+            "closed".to_string(),
+            writer,
+            latency.clone(),
+            move |progress| {
+                let _ = tx.send(progress);
+            },
+        );
+
+        queue
+            .push(vec![b'x'; 3 * WRITE_CHUNK_BYTES], Some(Instant::now()))
+            .unwrap();
+        let progress = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(progress.written, 0);
+        assert!(progress.error.is_some());
+        assert_eq!(queue.pending(), 0);
+        // Like a failed direct write, it isn't counted
+        assert!(latency.lock().unwrap().as_ref().unwrap().stats().is_none());
+    }
+}
@@ -907,6 +907,9 @@ pub fn run() {
                 }
             });
 
+            // Warm the terminal's shell detection; emits `pty-subsystem-ready`
+            terminal::init::init(app.handle());
+
             let ws_state = Arc::new(TokioMutex::new(WebSocketState::new()));
             app.manage(ws_state);
             let code_nav_state = CodeNavState(RwLock::new(CodeNavigationService::new()));
@@ -1032,10 +1035,64 @@ pub fn run() {
             websocket::ws_disconnect,
             execute_user_shell,
             execute_skill_script,
+            terminal::init::pty_subsystem_ready,
             terminal::pty_spawn,
+            terminal::validate::pty_validate_spawn,
+            terminal::spawn_wrapper::pty_set_spawn_wrapper,
+            terminal::spawn_wrapper::pty_get_spawn_wrapper,
+            terminal::global_env::pty_set_global_env,
+            terminal::global_env::pty_get_global_env,
+            terminal::global_env::pty_clear_global_env,
+            terminal::shells::pty_list_shells,
             terminal::pty_write,
+            terminal::pty_can_write,
+            terminal::pty_detect_hang,
+            terminal::pty_ack,
+            terminal::pty_pause,
+            terminal::pty_resume,
+            terminal::pty_wait_for,
+            terminal::ping::pty_ping,
+            terminal::run::pty_run_stream,
+            terminal::pty_change_shell,
+            terminal::spawn_like::pty_spawn_like,
+            terminal::split::pty_split,
+            terminal::pty_capture_next,
+            terminal::pty_get_info,
+            terminal::pty_runtime_stats,
+            terminal::pty_event_counts,
+            terminal::pty_cursor_position,
+            terminal::pty_parser_capabilities,
+            terminal::pty_set_answerback,
+            terminal::pty_get_answerback,
+            terminal::pty_set_write_latency_tracking,
+            terminal::pty_set_flush_interval,
+            terminal::pty_set_low_latency,
+            terminal::pty_set_emit_enabled,
+            terminal::pty_reattach,
+            terminal::foreground::pty_set_foreground,
+            terminal::encoding::pty_set_output_encoding,
+            terminal::pty_get_scrollback,
+            terminal::pty_scrollback_since_time,
+            terminal::pty_scrollback_for_command,
+            terminal::pty_set_scrollback_cap,
             terminal::pty_resize,
+            terminal::pty_send_remote_resize,
+            terminal::pty_effective_size,
+            terminal::pty_set_resize_floor,
+            terminal::pty_set_keepalive,
             terminal::pty_kill,
+            terminal::batch::pty_batch,
+            terminal::paste::pty_paste,
+            terminal::paste::pty_paste_confirm,
+            terminal::closures::pty_recent_closures,
+            terminal::closures::pty_clear_closures,
+            terminal::error_signatures::pty_set_error_signatures,
+            terminal::error_signatures::pty_get_error_signatures,
+            terminal::groups::pty_spawn_group,
+            terminal::groups::pty_list_group,
+            terminal::groups::pty_kill_group,
+            terminal::workspace::pty_export_workspace,
+            terminal::workspace::pty_import_workspace,
             code_navigation::code_nav_index_file,
             code_navigation::code_nav_index_files_batch,
             code_navigation::code_nav_find_definition,